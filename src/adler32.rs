@@ -0,0 +1,73 @@
+#![forbid(unsafe_code)]
+
+////////////////////////////////////////////////////////////////////////////////
+
+const MOD: u32 = 65521;
+
+/// Largest number of bytes which can be summed up before `b` may overflow `u32`.
+const MAX_CHUNK: usize = 5552;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Default for Adler32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Adler32 {
+    pub fn new() -> Self {
+        Self { a: 1, b: 0 }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for chunk in data.chunks(MAX_CHUNK) {
+            for byte in chunk {
+                self.a += u32::from(*byte);
+                self.b += self.a;
+            }
+            self.a %= MOD;
+            self.b %= MOD;
+        }
+    }
+
+    pub fn finalize(self) -> u32 {
+        (self.b << 16) | self.a
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn adler32(data: &[u8]) -> u32 {
+        let mut adler = Adler32::new();
+        adler.update(data);
+        adler.finalize()
+    }
+
+    #[test]
+    fn known_values() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"a"), 0x00620062);
+        assert_eq!(adler32(b"abc"), 0x024d0127);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+        assert_eq!(adler32(&[0xff; 100000]), 0x149a302c);
+    }
+
+    #[test]
+    fn incremental() {
+        let data: Vec<u8> = (0..20000u32).map(|i| (i * 31) as u8).collect();
+        let mut adler = Adler32::new();
+        for chunk in data.chunks(777) {
+            adler.update(chunk);
+        }
+        assert_eq!(adler.finalize(), adler32(&data));
+    }
+}
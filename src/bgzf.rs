@@ -0,0 +1,123 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+
+use log::*;
+
+use crate::{
+    error::{ensure, DecodeError, Result},
+    gzip::GzipReader,
+    tracking_writer::TrackingWriter,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Subfield ID of the BGZF block size.
+const BSIZE_ID: [u8; 2] = *b"BC";
+/// Most data a BGZF block may hold.
+const MAX_BLOCK_DATA: u64 = 64 * 1024;
+
+/// Reading from a `BgzfReader` yields the data of a BGZF file, such as a BAM file:
+/// gzip members of up to 64 KiB of data each, whose size is in a `BC` extra subfield.
+///
+/// Positions are virtual offsets, as in BAM indices: the offset of a block in the
+/// file shifted left by 16 bits, plus an offset within the data of the block.
+pub struct BgzfReader<R> {
+    reader: R,
+    /// Data of the current block, in the inner vector, of which `pos` bytes are read.
+    writer: TrackingWriter<Vec<u8>>,
+    pos: usize,
+    /// Offsets in the file of the current block and of the next one.
+    block_offset: u64,
+    next_offset: u64,
+}
+
+impl<R: BufRead> BgzfReader<R> {
+    /// `reader` is expected at the start of the file, block offsets count from there.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            writer: TrackingWriter::new(Vec::with_capacity(MAX_BLOCK_DATA as usize)),
+            pos: 0,
+            block_offset: 0,
+            next_offset: 0,
+        }
+    }
+
+    /// Virtual offset of the next byte to read.
+    pub fn virtual_offset(&self) -> u64 {
+        self.block_offset << 16 | self.pos as u64
+    }
+
+    /// Decode the next block. Returns `false` at the end of the file.
+    fn read_block(&mut self) -> Result<bool> {
+        if self.reader.fill_buf()?.is_empty() {
+            return Ok(false);
+        }
+        debug!("reading BGZF block at {}", self.next_offset);
+        self.writer.get_mut().clear();
+        self.pos = 0;
+        self.block_offset = self.next_offset;
+
+        let mut gz_reader = GzipReader::new(&mut self.reader);
+        let header = gz_reader.read_header()?;
+        let bsize = header
+            .extra_subfields()?
+            .into_iter()
+            .find(|subfield| subfield.id == BSIZE_ID && subfield.data.len() == 2)
+            .map(|subfield| u16::from_le_bytes([subfield.data[0], subfield.data[1]]))
+            .ok_or(DecodeError::InvalidHeader("missing BGZF block size"))?;
+        let (info, _) = gz_reader.decompress_member_into(&mut self.writer, true)?;
+        ensure!(
+            info.compressed_len == u64::from(bsize) + 1,
+            DecodeError::InvalidHeader("BGZF block size doesn't match the member")
+        );
+        ensure!(
+            info.uncompressed_len <= MAX_BLOCK_DATA,
+            DecodeError::InvalidHeader("BGZF block holds more than 64 KiB")
+        );
+        self.next_offset += info.compressed_len;
+        Ok(true)
+    }
+}
+
+impl<R: BufRead + Seek> BgzfReader<R> {
+    /// Move to `virtual_offset`, decoding the block it points into.
+    pub fn seek_to_block(&mut self, virtual_offset: u64) -> Result<()> {
+        let (block_offset, data_offset) = (virtual_offset >> 16, virtual_offset & 0xffff);
+        self.reader.seek(SeekFrom::Start(block_offset))?;
+        self.writer.get_mut().clear();
+        self.pos = 0;
+        self.block_offset = block_offset;
+        self.next_offset = block_offset;
+        self.read_block()?;
+        ensure!(
+            data_offset <= self.writer.get_ref().len() as u64,
+            DecodeError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "virtual offset past the end of its block",
+            ))
+        );
+        self.pos = data_offset as usize;
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for BgzfReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        /* Empty blocks, such as the one marking the end of the file, are skipped. */
+        while self.pos == self.writer.get_ref().len() {
+            if !self.read_block()? {
+                return Ok(0);
+            }
+        }
+        let data = &self.writer.get_ref()[self.pos..];
+        let len = buf.len().min(data.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
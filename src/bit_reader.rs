@@ -1,10 +1,10 @@
 #![forbid(unsafe_code)]
 
-use std::io::{self, BufRead};
+use crate::io::{self, BufRead};
 
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct BitSequence {
     bits: u16,
     len: u8,
@@ -27,6 +27,10 @@ impl BitSequence {
         self.len
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
     pub fn concat(self, other: Self) -> Self {
         assert!(other.len() + self.len <= 16);
         Self {
@@ -34,62 +38,235 @@ impl BitSequence {
             len: self.len + other.len,
         }
     }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Up to 64 bits in stream order, the first one being the lowest. Unlike the codes
+/// in `BitSequence`, these accumulate input rather than name a symbol.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BitBuffer {
+    bits: u64,
+    len: u8,
+}
+
+impl BitBuffer {
+    #[inline]
+    pub fn new(mut bits: u64, len: u8) -> Self {
+        assert!(len <= 64);
+        if len < 64 {
+            bits &= !(!0u64 << len);
+        }
+        Self { bits, len }
+    }
 
+    #[inline]
+    pub fn bits(&self) -> u64 {
+        self.bits
+    }
+
+    #[inline]
+    pub fn len(&self) -> u8 {
+        self.len
+    }
+
+    /// Append the bits of `other`, which come after those of `self` in the stream.
+    #[inline]
+    pub fn concat(self, other: Self) -> Self {
+        assert!(other.len + self.len <= 64);
+        Self {
+            bits: self.bits | other.bits.checked_shl(self.len.into()).unwrap_or(0),
+            len: self.len + other.len,
+        }
+    }
+
+    /// Remove and return the first `len` bits.
+    #[inline]
     pub fn consume(&mut self, len: u8) -> Self {
         assert!(self.len >= len);
 
-        let bits = self.bits & !(!0 << len);
+        let bits = Self::new(self.bits, len);
         self.len -= len;
-        self.bits >>= len;
+        self.bits = self.bits.checked_shr(len.into()).unwrap_or(0);
 
-        BitSequence::new(bits, len)
+        bits
     }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
+#[derive(Clone)]
 pub struct BitReader<T> {
     stream: T,
-    remainder: BitSequence,
+    /// Unread bits.
+    buffer: BitBuffer,
+    /// Number of bytes at the front of the stream's buffer which are loaded into
+    /// `buffer`, but not consumed from the stream yet. Bytes are consumed lazily so that
+    /// the stream doesn't advance past the byte holding the next unread bit.
+    loaded: usize,
+    /// Number of the lowest unread bits coming from bytes already consumed from the
+    /// stream. Only lookahead across the end of the stream's buffer leaves such bits.
+    owned: u8,
+    /// Number of bytes consumed from the stream by the reader itself.
+    consumed: u64,
 }
 
 impl<T: BufRead> BitReader<T> {
     pub fn new(stream: T) -> Self {
         Self {
             stream,
-            remainder: BitSequence::new(0, 0),
+            buffer: BitBuffer::default(),
+            loaded: 0,
+            owned: 0,
+            consumed: 0,
         }
     }
 
     pub fn read_bits(&mut self, len: u8) -> io::Result<BitSequence> {
         assert!(len <= 16 && len != 0);
-        if self.remainder.len() >= len {
-            return Ok(self.remainder.consume(len));
-        }
+        self.ensure_buffered(len)?;
+        let bits = BitSequence::new(self.buffer.bits() as u16, len);
+        self.skip_bits(len);
+        Ok(bits)
+    }
 
-        let to_fill: u8 = len - self.remainder.len();
+    /// Read `len` bits (up to 32) as an integer, the first read bit being the lowest one.
+    pub fn read_bits_u32(&mut self, len: u8) -> io::Result<u32> {
+        assert!(len <= 32);
+        self.ensure_buffered(len)?;
+        let bits = BitBuffer::new(self.buffer.bits(), len).bits() as u32;
+        self.skip_bits(len);
+        Ok(bits)
+    }
+
+    /// Return the next `len` bits without consuming them. Fewer bits are returned only
+    /// if the stream ends earlier.
+    pub fn peek_bits(&mut self, len: u8) -> io::Result<BitSequence> {
+        assert!(len <= 16);
+        if self.buffer.len() < len {
+            self.refill(len)?;
+        }
+        Ok(BitSequence::new(
+            self.buffer.bits() as u16,
+            self.buffer.len().min(len),
+        ))
+    }
 
-        let mut byte = vec![0u8; ((to_fill - 1) / 8 + 1).into()];
-        self.stream.read_exact(&mut byte)?;
-        let mut bits = if byte.len() == 1 {
-            BitSequence::new(byte[0].into(), 8)
-        } else {
-            BitSequence::new(((byte[1] as u16) << 8) + byte[0] as u16, 16)
-        };
+    /// Consume `len` bits which have already been peeked.
+    pub fn skip_bits(&mut self, len: u8) {
+        self.buffer.consume(len);
+        self.owned = self.owned.saturating_sub(len);
+    }
 
-        let to_read = bits.consume(to_fill).concat(self.remainder);
-        self.remainder = bits;
+    /// Discard the unread bits in the current byte, if any, and return their number.
+    /// Unlike `borrow_reader_from_boundary`, reading bits goes on as usual.
+    pub fn align_to_byte(&mut self) -> u8 {
+        /* Only whole bytes are loaded, so the partial byte is at the bottom. */
+        let dropped = self.buffer.len() % 8;
+        self.skip_bits(dropped);
+        dropped
+    }
 
-        Ok(to_read)
+    /// Discard the unread bits in the current byte, if any, and return them, e.g. to
+    /// check the padding after the final block.
+    pub fn take_partial_byte(&mut self) -> BitSequence {
+        let len = self.buffer.len() % 8;
+        let bits = BitSequence::new(self.buffer.bits() as u16, len);
+        self.skip_bits(len);
+        bits
     }
 
     /// Discard all the unread bits in the current byte and return a mutable reference
-    /// to the underlying reader.
-    pub fn borrow_reader_from_boundary(&mut self) -> &mut T {
-        assert!(self.remainder.len() <= 8);
-        self.remainder.consume(self.remainder.len());
+    /// to the underlying reader, positioned at the first byte with no bits read.
+    ///
+    /// Fails without changing anything if whole unread bytes were already consumed
+    /// from the reader, which only happens if bits were peeked across the end of the
+    /// reader's buffer and not skipped afterwards.
+    pub fn borrow_reader_from_boundary(&mut self) -> io::Result<&mut T> {
+        if self.owned >= 8 {
+            return Err(io::Error::other(
+                "unread bytes are already consumed from the underlying reader",
+            ));
+        }
+        let unread_bytes = usize::from(self.buffer.len() / 8);
+        self.stream.consume(self.loaded - unread_bytes);
+        self.consumed += (self.loaded - unread_bytes) as u64;
+        self.buffer = BitBuffer::default();
+        self.loaded = 0;
+        self.owned = 0;
+        Ok(&mut self.stream)
+    }
+
+    /// Mutable reference to the underlying reader. Only appending to its buffer is
+    /// safe, as the bytes at the front may be loaded but not consumed yet.
+    pub fn get_mut(&mut self) -> &mut T {
         &mut self.stream
     }
+
+    /// Discard the unread bits in the current byte and return the underlying reader.
+    /// Fails in the same cases as `borrow_reader_from_boundary`.
+    pub fn into_inner(mut self) -> io::Result<T> {
+        self.borrow_reader_from_boundary()?;
+        Ok(self.stream)
+    }
+
+    /// Number of unread bits, here or in the buffer of the underlying reader. Fills the
+    /// buffer of the underlying reader if it is empty.
+    pub fn buffered_bits(&mut self) -> io::Result<u64> {
+        let buf_len = self.stream.fill_buf()?.len();
+        Ok(u64::from(self.buffer.len()) + (buf_len - self.loaded) as u64 * 8)
+    }
+
+    /// Number of bytes this reader has consumed from the underlying one. Bytes read
+    /// through `borrow_reader_from_boundary` aren't counted.
+    ///
+    /// As bytes are consumed lazily, this may lag behind `bit_position`, or run ahead
+    /// of it after a lookahead across the end of the underlying buffer.
+    pub fn bytes_consumed(&self) -> u64 {
+        self.consumed
+    }
+
+    /// Number of bits read so far, counting the bits discarded when moving to a byte
+    /// boundary. Bytes read through `borrow_reader_from_boundary` aren't counted.
+    pub fn bit_position(&self) -> u64 {
+        (self.consumed + self.loaded as u64) * 8 - u64::from(self.buffer.len())
+    }
+
+    fn ensure_buffered(&mut self, len: u8) -> io::Result<()> {
+        if self.buffer.len() < len {
+            self.refill(len)?;
+            if self.buffer.len() < len {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Load bytes until at least `len` bits are buffered or the stream ends.
+    fn refill(&mut self, len: u8) -> io::Result<()> {
+        while self.buffer.len() < len {
+            let buf = self.stream.fill_buf()?;
+            if self.loaded == buf.len() {
+                /* The stream's buffer is exhausted, so its bytes have to be consumed. */
+                self.stream.consume(self.loaded);
+                self.consumed += self.loaded as u64;
+                self.loaded = 0;
+                self.owned = self.buffer.len();
+                if self.stream.fill_buf()?.is_empty() {
+                    break;
+                }
+                continue;
+            }
+            for byte in &buf[self.loaded..] {
+                if self.buffer.len() > 56 {
+                    break;
+                }
+                self.buffer = self.buffer.concat(BitBuffer::new((*byte).into(), 8));
+                self.loaded += 1;
+            }
+        }
+        Ok(())
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -99,6 +276,25 @@ mod tests {
     use super::*;
     use byteorder::ReadBytesExt;
 
+    #[test]
+    fn bit_buffer() {
+        let mut buffer = BitBuffer::new(0xff, 4)
+            .concat(BitBuffer::new(0b10, 2))
+            .concat(BitBuffer::new(!0, 58));
+        assert_eq!(buffer.len(), 64);
+        assert_eq!(buffer.bits(), !0 << 6 | 0b10_1111);
+
+        assert_eq!(buffer.consume(4), BitBuffer::new(0b1111, 4));
+        assert_eq!(buffer.consume(2), BitBuffer::new(0b10, 2));
+        assert_eq!(buffer.consume(0), BitBuffer::default());
+        assert_eq!(buffer.consume(58), BitBuffer::new(!0, 58));
+        assert_eq!(buffer, BitBuffer::default());
+
+        let mut buffer = BitBuffer::new(0x0123_4567_89ab_cdef, 64);
+        assert_eq!(buffer.consume(64).bits(), 0x0123_4567_89ab_cdef);
+        assert_eq!(buffer.bits(), 0);
+    }
+
     #[test]
     fn read_bits() -> io::Result<()> {
         let data: &[u8] = &[0b01100011, 0b01011011, 0b10101111];
@@ -116,12 +312,183 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn read_bits_u32() -> io::Result<()> {
+        let data: &[u8] = &[0x78, 0x56, 0x34, 0x12, 0b10101111, 0xff, 0x00, 0xff, 0x01];
+        let mut reader = BitReader::new(data);
+        assert_eq!(reader.read_bits_u32(32)?, 0x12345678);
+        assert_eq!(reader.read_bits_u32(0)?, 0);
+        assert_eq!(reader.read_bits(3)?, BitSequence::new(0b111, 3));
+        assert_eq!(reader.read_bits_u32(30)?, 0x3fe0_1ff5);
+        assert_eq!(
+            reader.read_bits_u32(8).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        Ok(())
+    }
+
+    /// Serves the data in chunks of `chunk` bytes.
+    struct ChunkedReader<'a> {
+        data: &'a [u8],
+        chunk: usize,
+    }
+
+    impl io::Read for ChunkedReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = self.fill_buf()?.len().min(buf.len());
+            buf[..len].copy_from_slice(&self.data[..len]);
+            self.consume(len);
+            Ok(len)
+        }
+    }
+
+    impl BufRead for ChunkedReader<'_> {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            Ok(&self.data[..self.chunk.min(self.data.len())])
+        }
+
+        fn consume(&mut self, amt: usize) {
+            self.data = &self.data[amt..];
+        }
+    }
+
+    #[test]
+    fn read_bits_across_chunks() -> io::Result<()> {
+        let data: Vec<u8> = (0..200u32).map(|i| (i * 167 + 13) as u8).collect();
+        let expected_bit = |pos: usize| (data[pos / 8] >> (pos % 8)) & 1;
+
+        for chunk in [1, 2, 3, 7, 8, 9, 200] {
+            let mut reader = BitReader::new(ChunkedReader { data: &data, chunk });
+            let mut pos = 0;
+            for len in (1..=16).cycle() {
+                if pos + len as usize > data.len() * 8 {
+                    break;
+                }
+                let expected = (0..len as usize)
+                    .rev()
+                    .fold(0u16, |acc, i| (acc << 1) | expected_bit(pos + i) as u16);
+                assert_eq!(reader.read_bits(len)?, BitSequence::new(expected, len));
+                pos += len as usize;
+                assert_eq!(reader.bit_position(), pos as u64);
+            }
+            assert_eq!(
+                reader.read_bits(16).unwrap_err().kind(),
+                io::ErrorKind::UnexpectedEof
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn bit_position() -> io::Result<()> {
+        let data: Vec<u8> = (0..16u8).collect();
+        for chunk in [1, 3, 16] {
+            let mut reader = BitReader::new(ChunkedReader { data: &data, chunk });
+            assert_eq!(reader.bit_position(), 0);
+            reader.read_bits(3)?;
+            reader.read_bits(9)?;
+            assert_eq!(reader.bit_position(), 12);
+
+            reader.borrow_reader_from_boundary()?;
+            assert_eq!(reader.bit_position(), 16);
+            assert_eq!(reader.bytes_consumed(), 2);
+            reader.read_bits_u32(20)?;
+            assert_eq!(reader.bit_position(), 36);
+            reader.peek_bits(16)?;
+            assert_eq!(reader.bit_position(), 36);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn align_to_byte() -> io::Result<()> {
+        let data: &[u8] = &[0b10101111, 0x42, 0x24];
+        let mut reader = BitReader::new(data);
+        assert_eq!(reader.align_to_byte(), 0);
+        assert_eq!(reader.read_bits(3)?, BitSequence::new(0b111, 3));
+        assert_eq!(reader.align_to_byte(), 5);
+        assert_eq!(reader.bit_position(), 8);
+        assert_eq!(reader.align_to_byte(), 0);
+        assert_eq!(reader.read_bits(8)?.bits(), 0x42);
+        assert_eq!(reader.align_to_byte(), 0);
+        assert_eq!(reader.bit_position(), 16);
+        reader.read_bits(1)?;
+        assert_eq!(reader.align_to_byte(), 7);
+        assert_eq!(
+            reader.read_bits(1).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn take_partial_byte() -> io::Result<()> {
+        let data: &[u8] = &[0b10101111, 0x42];
+        let mut reader = BitReader::new(data);
+        assert_eq!(reader.take_partial_byte(), BitSequence::new(0, 0));
+        reader.read_bits(3)?;
+        assert_eq!(reader.take_partial_byte(), BitSequence::new(0b10101, 5));
+        assert_eq!(reader.bit_position(), 8);
+        assert_eq!(reader.take_partial_byte(), BitSequence::new(0, 0));
+        assert_eq!(reader.read_bits(8)?.bits(), 0x42);
+        Ok(())
+    }
+
+    #[test]
+    fn peek_bits() -> io::Result<()> {
+        let data: Vec<u8> = (0..64u32).map(|i| (i * 89 + 7) as u8).collect();
+        for chunk in [1, 2, 5, 64] {
+            let mut reader = BitReader::new(ChunkedReader { data: &data, chunk });
+            let mut peeker = BitReader::new(ChunkedReader { data: &data, chunk });
+            for len in (1..=16).cycle().take(45) {
+                let peeked = peeker.peek_bits(16)?;
+                assert_eq!(peeked.len(), 16);
+                assert_eq!(peeker.peek_bits(len)?, reader.read_bits(len)?);
+                peeker.skip_bits(len);
+            }
+        }
+
+        let data: &[u8] = &[0b01100011, 0b11011011, 0b10101111];
+        let mut reader = BitReader::new(ChunkedReader { data, chunk: 1 });
+        reader.read_bits_u32(20)?;
+        assert_eq!(reader.peek_bits(16)?, BitSequence::new(0b1010, 4));
+        Ok(())
+    }
+
+    #[test]
+    fn borrow_reader_after_peek() -> io::Result<()> {
+        let data: &[u8] = &[0b01100011, 0b11011011, 0b10101111];
+        let mut reader = BitReader::new(ChunkedReader { data, chunk: 1 });
+        assert_eq!(reader.peek_bits(9)?, BitSequence::new(0b101100011, 9));
+        reader.skip_bits(3);
+        assert_eq!(reader.borrow_reader_from_boundary()?.read_u8()?, 0b11011011);
+        assert_eq!(reader.read_bits(8)?, BitSequence::new(0b10101111, 8));
+        Ok(())
+    }
+
+    #[test]
+    fn borrow_reader_after_unskipped_peek() -> io::Result<()> {
+        let data: &[u8] = &[0b01100011, 0b11011011, 0b10101111];
+        let mut reader = BitReader::new(ChunkedReader { data, chunk: 1 });
+        assert_eq!(
+            reader.peek_bits(16)?,
+            BitSequence::new(0b1101101101100011, 16)
+        );
+        assert!(reader.borrow_reader_from_boundary().is_err());
+
+        /* The failed call changes nothing, so the reader can still be used. */
+        assert_eq!(reader.read_bits(3)?, BitSequence::new(0b011, 3));
+        assert_eq!(reader.borrow_reader_from_boundary()?.read_u8()?, 0b11011011);
+        assert_eq!(reader.read_bits(8)?, BitSequence::new(0b10101111, 8));
+        Ok(())
+    }
+
     #[test]
     fn borrow_reader_from_boundary() -> io::Result<()> {
         let data: &[u8] = &[0b01100011, 0b11011011, 0b10101111];
         let mut reader = BitReader::new(data);
         assert_eq!(reader.read_bits(3)?, BitSequence::new(0b011, 3));
-        assert_eq!(reader.borrow_reader_from_boundary().read_u8()?, 0b11011011);
+        assert_eq!(reader.borrow_reader_from_boundary()?.read_u8()?, 0b11011011);
         assert_eq!(reader.read_bits(8)?, BitSequence::new(0b10101111, 8));
         Ok(())
     }
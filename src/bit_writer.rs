@@ -0,0 +1,115 @@
+#![forbid(unsafe_code)]
+
+use crate::io::{self, Write};
+
+use crate::bit_reader::BitSequence;
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct BitWriter<T> {
+    stream: T,
+    /// Bits not written to the stream yet, the first one being the lowest.
+    buffer: u64,
+    buffer_len: u8,
+}
+
+impl<T: Write> BitWriter<T> {
+    pub fn new(stream: T) -> Self {
+        Self {
+            stream,
+            buffer: 0,
+            buffer_len: 0,
+        }
+    }
+
+    /// Write the `len` (up to 32) lowest bits of `bits`, the lowest one first.
+    pub fn write_bits(&mut self, bits: u32, len: u8) -> io::Result<()> {
+        assert!(len <= 32);
+        self.buffer |= (u64::from(bits) & !(!0u64 << len)) << self.buffer_len;
+        self.buffer_len += len;
+        if self.buffer_len >= 32 {
+            self.stream.write_all(&(self.buffer as u32).to_le_bytes())?;
+            self.buffer >>= 32;
+            self.buffer_len -= 32;
+        }
+        Ok(())
+    }
+
+    /// Write a Huffman code, its highest bit first.
+    pub fn write_code(&mut self, code: BitSequence) -> io::Result<()> {
+        if code.is_empty() {
+            return Ok(());
+        }
+        let reversed = code.bits().reverse_bits() >> (16 - code.len());
+        self.write_bits(reversed.into(), code.len())
+    }
+
+    /// Pad the current byte with zero bits and return a mutable reference to the
+    /// underlying writer.
+    pub fn borrow_writer_from_boundary(&mut self) -> io::Result<&mut T> {
+        let len = usize::from(self.buffer_len.div_ceil(8));
+        self.stream.write_all(&self.buffer.to_le_bytes()[..len])?;
+        self.buffer = 0;
+        self.buffer_len = 0;
+        Ok(&mut self.stream)
+    }
+
+    /// Pad the current byte with zero bits and return the underlying writer.
+    pub fn into_inner(mut self) -> io::Result<T> {
+        self.borrow_writer_from_boundary()?;
+        Ok(self.stream)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_reader::BitReader;
+
+    #[test]
+    fn write_bits() -> io::Result<()> {
+        let mut writer = BitWriter::new(vec![]);
+        writer.write_bits(0b1, 1)?;
+        writer.write_bits(0b01, 2)?;
+        writer.write_bits(0b100, 3)?;
+        writer.write_bits(0b1101, 4)?;
+        writer.write_bits(0b10101, 5)?;
+        writer.write_bits(0b01110, 5)?;
+        writer.write_bits(0xdead_beef, 32)?;
+        let output = writer.into_inner()?;
+
+        let mut reader = BitReader::new(output.as_slice());
+        assert_eq!(reader.read_bits(1)?, BitSequence::new(0b1, 1));
+        assert_eq!(reader.read_bits(2)?, BitSequence::new(0b01, 2));
+        assert_eq!(reader.read_bits(3)?, BitSequence::new(0b100, 3));
+        assert_eq!(reader.read_bits(4)?, BitSequence::new(0b1101, 4));
+        assert_eq!(reader.read_bits(5)?, BitSequence::new(0b10101, 5));
+        assert_eq!(reader.read_bits(5)?, BitSequence::new(0b01110, 5));
+        assert_eq!(reader.read_bits_u32(32)?, 0xdead_beef);
+        assert_eq!(reader.read_bits(4)?, BitSequence::new(0, 4));
+        assert!(reader.read_bits(1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn write_code() -> io::Result<()> {
+        let mut writer = BitWriter::new(vec![]);
+        writer.write_code(BitSequence::new(0b110, 3))?;
+        writer.write_code(BitSequence::new(0b0111, 4))?;
+        assert_eq!(writer.into_inner()?, [0b01110011]);
+        Ok(())
+    }
+
+    #[test]
+    fn borrow_writer_from_boundary() -> io::Result<()> {
+        let mut writer = BitWriter::new(vec![]);
+        writer.write_bits(0b011, 3)?;
+        writer.borrow_writer_from_boundary()?.write_all(&[0xab])?;
+        writer.borrow_writer_from_boundary()?.write_all(&[0xcd])?;
+        writer.write_bits(0b1, 1)?;
+        assert_eq!(writer.into_inner()?, [0b011, 0xab, 0xcd, 0b1]);
+        Ok(())
+    }
+}
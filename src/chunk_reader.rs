@@ -0,0 +1,83 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, BufRead, Read};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Buffered reader over the byte chunks produced by an iterator, e.g. to feed
+/// `decompress` from a source which hands out `Vec<u8>`s.
+pub struct ChunkReader<I> {
+    chunks: I,
+    /// Current chunk, of which the first `pos` bytes are consumed.
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl<I: Iterator<Item = Vec<u8>>> ChunkReader<I> {
+    pub fn new<T: IntoIterator<IntoIter = I>>(chunks: T) -> Self {
+        Self {
+            chunks: chunks.into_iter(),
+            chunk: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl<I: Iterator<Item = Vec<u8>>> Read for ChunkReader<I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.fill_buf()?;
+        let len = buf.len().min(data.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<I: Iterator<Item = Vec<u8>>> BufRead for ChunkReader<I> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        /* Empty chunks don't mean the end of the input, only running out of chunks does. */
+        while self.pos == self.chunk.len() {
+            match self.chunks.next() {
+                Some(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                None => break,
+            }
+        }
+        Ok(&self.chunk[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.chunk.len());
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read() -> io::Result<()> {
+        let chunks = vec![
+            b"ab".to_vec(),
+            vec![],
+            b"c".to_vec(),
+            vec![],
+            b"def".to_vec(),
+        ];
+        let mut reader = ChunkReader::new(chunks);
+        assert_eq!(reader.fill_buf()?, b"ab");
+        reader.consume(1);
+        assert_eq!(reader.fill_buf()?, b"b");
+        reader.consume(1);
+        assert_eq!(reader.fill_buf()?, b"c");
+        let mut rest = vec![];
+        reader.read_to_end(&mut rest)?;
+        assert_eq!(rest, b"cdef");
+        assert!(reader.fill_buf()?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, Write};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Writer that hands the data to `sink` in chunks of `chunk_size` bytes, except for
+/// the chunks cut short by `flush` or `finish`.
+pub struct ChunkWriter<F> {
+    sink: F,
+    buffer: Vec<u8>,
+    chunk_size: usize,
+}
+
+impl<F: FnMut(&[u8])> Write for ChunkWriter<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.chunk_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        if self.buffer.len() == self.chunk_size {
+            self.flush()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            (self.sink)(&self.buffer);
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+}
+
+impl<F: FnMut(&[u8])> ChunkWriter<F> {
+    pub fn new(sink: F, chunk_size: usize) -> Self {
+        assert!(chunk_size != 0);
+        Self {
+            sink,
+            buffer: Vec::with_capacity(chunk_size),
+            chunk_size,
+        }
+    }
+
+    /// Hand out the data still buffered.
+    pub fn finish(mut self) {
+        /* Flushing into `sink` can't fail. */
+        let _ = self.flush();
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write() -> io::Result<()> {
+        let mut chunks = vec![];
+        let mut writer = ChunkWriter::new(|chunk: &[u8]| chunks.push(chunk.to_vec()), 4);
+        writer.write_all(b"ab")?;
+        writer.write_all(b"cdefghij")?;
+        writer.flush()?;
+        writer.write_all(b"k")?;
+        writer.finish();
+        assert_eq!(chunks, [&b"abcd"[..], b"efgh", b"ij", b"k"]);
+        Ok(())
+    }
+}
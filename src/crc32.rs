@@ -0,0 +1,116 @@
+#![forbid(unsafe_code)]
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Reversed polynomial of the CRC-32 used by gzip.
+const POLYNOMIAL: u32 = 0xedb88320;
+
+/// A 32x32 matrix over GF(2), one column per entry.
+type Matrix = [u32; 32];
+
+fn times(matrix: &Matrix, mut vector: u32) -> u32 {
+    let mut sum = 0;
+    let mut column = 0;
+    while vector != 0 {
+        if vector & 1 != 0 {
+            sum ^= matrix[column];
+        }
+        vector >>= 1;
+        column += 1;
+    }
+    sum
+}
+
+fn square(matrix: &Matrix) -> Matrix {
+    let mut squared = [0; 32];
+    for (column, entry) in squared.iter_mut().enumerate() {
+        *entry = times(matrix, matrix[column]);
+    }
+    squared
+}
+
+/// CRC-32 of the concatenation of two byte sequences, given the CRC-32 of each and
+/// the length of the second one.
+pub fn crc32_combine(crc1: u32, crc2: u32, len2: u64) -> u32 {
+    if len2 == 0 {
+        return crc1;
+    }
+
+    /* The operator appending a single zero bit to the message. */
+    let mut odd = [0; 32];
+    odd[0] = POLYNOMIAL;
+    for (n, entry) in odd.iter_mut().enumerate().skip(1) {
+        *entry = 1 << (n - 1);
+    }
+    /* Square it into the operators for two and four zero bits. */
+    let mut even = square(&odd);
+    odd = square(&even);
+
+    /*
+     * Feed `len2` zero bytes to the first CRC-32, one squared operator per bit of
+     * `len2`. What remains of the concatenation is the second CRC-32.
+     */
+    let mut crc = crc1;
+    let mut len = len2;
+    loop {
+        even = square(&odd);
+        if len & 1 != 0 {
+            crc = times(&even, crc);
+        }
+        len >>= 1;
+        if len == 0 {
+            break;
+        }
+
+        odd = square(&even);
+        if len & 1 != 0 {
+            crc = times(&odd, crc);
+        }
+        len >>= 1;
+        if len == 0 {
+            break;
+        }
+    }
+    crc ^ crc2
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+    #[test]
+    fn combine_halves() {
+        let data = b"The quick brown fox jumps over the lazy dog";
+        assert_eq!(CRC.checksum(data), 0x414fa339);
+
+        for mid in 0..=data.len() {
+            let (first, second) = data.split_at(mid);
+            assert_eq!(
+                crc32_combine(
+                    CRC.checksum(first),
+                    CRC.checksum(second),
+                    second.len() as u64
+                ),
+                0x414fa339
+            );
+        }
+    }
+
+    #[test]
+    fn combine_long() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i * 7 % 253) as u8).collect();
+        let (first, second) = data.split_at(31_337);
+        assert_eq!(
+            crc32_combine(
+                CRC.checksum(first),
+                CRC.checksum(second),
+                second.len() as u64
+            ),
+            CRC.checksum(&data)
+        );
+    }
+}
@@ -0,0 +1,420 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, BufRead, Read};
+
+use log::*;
+
+use crate::{
+    bit_reader::BitReader,
+    deflate::DeflateReader,
+    error::{DecodeError, Result},
+    gzip::{GzipReader, HeaderEncoding, MemberFooter, FOOTER_LEN},
+    tracking_writer::TrackingWriter,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Amount of output decoded at once before it is handed out by `read`.
+const CHUNK_SIZE: usize = 32 * 1024;
+
+/// Decoded data, of which the first `pos` bytes are already read.
+#[derive(Default)]
+struct OutputBuffer {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl OutputBuffer {
+    /// Read the data decoded so far into `buf`, refilling it with `decode_more` once
+    /// it is all read, until `decode_more` returns `false` at the end of the stream.
+    fn read(
+        &mut self,
+        buf: &mut [u8],
+        mut decode_more: impl FnMut(&mut Vec<u8>) -> Result<bool>,
+    ) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while self.pos == self.data.len() {
+            self.data.clear();
+            self.pos = 0;
+            if !decode_more(&mut self.data)? {
+                return Ok(0);
+            }
+        }
+        let len = buf.len().min(self.data.len() - self.pos);
+        buf[..len].copy_from_slice(&self.data[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// The deflate stream and footer of a member, decoded a chunk at a time.
+struct MemberDecoder<R> {
+    deflate_reader: Box<DeflateReader<R>>,
+    writer: TrackingWriter<Vec<u8>>,
+}
+
+impl<R: BufRead> MemberDecoder<R> {
+    /// Parse the header of the member, then decode it with the options of `gz_reader`.
+    fn start(mut gz_reader: GzipReader<R>) -> Result<Self> {
+        gz_reader.read_header()?;
+        Ok(Self {
+            deflate_reader: Box::new(gz_reader.into_deflate_reader()),
+            writer: TrackingWriter::new(Vec::new()),
+        })
+    }
+
+    /// Decode the next chunk into the empty `output`. Returns `false` at the end of
+    /// the deflate stream, which may come with or without a last chunk.
+    fn decode_chunk(&mut self, output: &mut Vec<u8>) -> Result<bool> {
+        let has_more = self
+            .deflate_reader
+            .decode_some(&mut self.writer, CHUNK_SIZE)?;
+        /* The emptied `output` takes the place of the chunk in the writer. */
+        std::mem::swap(output, self.writer.get_mut());
+        Ok(has_more)
+    }
+
+    /// Check the footer once the deflate stream is decoded, returning the reader
+    /// right after it.
+    fn finish(self) -> Result<R> {
+        let byte_count = self.writer.byte_count();
+        let crc = self.writer.current_crc32();
+        let mut reader = self.deflate_reader.into_inner()?;
+        MemberFooter::read(&mut reader)?.verify(byte_count, Some(crc))?;
+        Ok(reader)
+    }
+}
+
+fn already_failed() -> DecodeError {
+    DecodeError::Io(io::Error::other("decoder has already failed"))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+enum State<R> {
+    /// Between members, the next byte being either a member header or the end.
+    Boundary(R),
+    Member(MemberDecoder<R>),
+    Done,
+    /// An earlier call has failed, so the position in the stream is unknown.
+    Failed,
+}
+
+/// Pull-based gzip decompression: reading from a `GzipDecoder` yields the
+/// decompressed contents of all the members in the underlying stream.
+pub struct GzipDecoder<R> {
+    state: State<R>,
+    output: OutputBuffer,
+}
+
+impl<R: BufRead> GzipDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            state: State::Boundary(reader),
+            output: OutputBuffer::default(),
+        }
+    }
+
+    /// CRC-32 of the current member's data decoded so far, including the data which
+    /// is decoded but not read yet. `None` between members.
+    pub fn current_crc32(&self) -> Option<u32> {
+        match &self.state {
+            State::Member(decoder) => Some(decoder.writer.current_crc32()),
+            _ => None,
+        }
+    }
+
+    /// Fill the empty `output` with the next decoded chunk. Returns `false` at the end
+    /// of the stream.
+    fn decode_more(state: &mut State<R>, output: &mut Vec<u8>) -> Result<bool> {
+        loop {
+            match std::mem::replace(state, State::Failed) {
+                State::Boundary(mut reader) => {
+                    if reader.fill_buf()?.is_empty() {
+                        *state = State::Done;
+                        return Ok(false);
+                    }
+                    *state = State::Member(MemberDecoder::start(GzipReader::new(reader))?);
+                }
+                State::Member(mut decoder) => {
+                    *state = if decoder.decode_chunk(output)? {
+                        State::Member(decoder)
+                    } else {
+                        State::Boundary(decoder.finish()?)
+                    };
+                    if !output.is_empty() {
+                        return Ok(true);
+                    }
+                }
+                State::Done => {
+                    *state = State::Done;
+                    return Ok(false);
+                }
+                State::Failed => return Err(already_failed()),
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Read for GzipDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let state = &mut self.state;
+        self.output
+            .read(buf, |output| Self::decode_more(state, output))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+enum MemberState<R> {
+    /// The header isn't parsed yet.
+    Start(GzipReader<R>),
+    Body(MemberDecoder<R>),
+    /// The footer is checked, the reader is right after it.
+    Done(R),
+    Failed,
+}
+
+/// Reading from a `MemberReader` yields the decompressed data of a single member,
+/// see `GzipReader::member_reader`.
+pub struct MemberReader<R> {
+    state: MemberState<R>,
+    output: OutputBuffer,
+}
+
+impl<R: BufRead> MemberReader<R> {
+    pub(crate) fn new(gz_reader: GzipReader<R>) -> Self {
+        Self {
+            state: MemberState::Start(gz_reader),
+            output: OutputBuffer::default(),
+        }
+    }
+
+    /// The underlying reader, positioned right after the footer, once the member
+    /// is read to the end.
+    pub fn into_inner(self) -> Option<R> {
+        match self.state {
+            MemberState::Done(reader) => Some(reader),
+            _ => None,
+        }
+    }
+
+    /// Fill the empty `output` with the next decoded chunk. Returns `false` at the end
+    /// of the member.
+    fn decode_more(state: &mut MemberState<R>, output: &mut Vec<u8>) -> Result<bool> {
+        loop {
+            match std::mem::replace(state, MemberState::Failed) {
+                MemberState::Start(gz_reader) => {
+                    *state = MemberState::Body(MemberDecoder::start(gz_reader)?);
+                }
+                MemberState::Body(mut decoder) => {
+                    *state = if decoder.decode_chunk(output)? {
+                        MemberState::Body(decoder)
+                    } else {
+                        MemberState::Done(decoder.finish()?)
+                    };
+                    if !output.is_empty() {
+                        return Ok(true);
+                    }
+                }
+                MemberState::Done(reader) => {
+                    *state = MemberState::Done(reader);
+                    return Ok(false);
+                }
+                MemberState::Failed => return Err(already_failed()),
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Read for MemberReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let state = &mut self.state;
+        self.output
+            .read(buf, |output| Self::decode_more(state, output))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Input pushed into a `DecodeState` which isn't consumed yet.
+#[derive(Default)]
+struct PendingInput {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl PendingInput {
+    fn len(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    /// Drop the consumed bytes, so that they aren't kept along with the rest.
+    fn compact(&mut self) {
+        self.data.drain(..self.pos);
+        self.pos = 0;
+    }
+}
+
+impl Read for PendingInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.len());
+        buf[..len].copy_from_slice(&self.data[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+impl BufRead for PendingInput {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        Ok(&self.data[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+enum PushState {
+    /// Between members, the next bytes being either a member header or the end.
+    Boundary(PendingInput),
+    Member {
+        deflate_reader: Box<DeflateReader<PendingInput>>,
+        writer: TrackingWriter<Vec<u8>>,
+    },
+    /// The deflate stream of the member is done, its footer comes next.
+    Footer {
+        input: PendingInput,
+        byte_count: u64,
+        crc: u32,
+    },
+    /// An earlier call has failed, so the position in the stream is unknown.
+    Failed,
+}
+
+/// Push-based gzip decompression, for input arriving in arbitrary chunks: `push`
+/// returns the data decoded from the input so far, and `finish` the rest once all
+/// the input is there.
+///
+/// Decoding stops before a header, symbol or footer which may not be entirely in the
+/// input pushed so far, and goes on from there once more input is pushed. So a few
+/// hundred bytes of input may stay pending until the next `push` or `finish`.
+pub struct DecodeState {
+    state: PushState,
+}
+
+impl DecodeState {
+    pub fn new() -> Self {
+        Self {
+            state: PushState::Boundary(PendingInput::default()),
+        }
+    }
+
+    /// Add `input` to the stream and return the data which can be decoded now.
+    pub fn push(&mut self, input: &[u8]) -> Result<Vec<u8>> {
+        let pending = self.pending_input().ok_or_else(already_failed)?;
+        pending.compact();
+        pending.data.extend_from_slice(input);
+        let mut output = vec![];
+        self.decode(&mut output, true)?;
+        Ok(output)
+    }
+
+    /// Decode the rest of the stream, which ends with the input pushed so far,
+    /// checking the footer of the last member.
+    pub fn finish(mut self) -> Result<Vec<u8>> {
+        let mut output = vec![];
+        self.decode(&mut output, false)?;
+        Ok(output)
+    }
+
+    /// Decode into `output` until the pending input runs out, or with `wait` set, until
+    /// it may run out in the middle of the next step.
+    fn decode(&mut self, output: &mut Vec<u8>, wait: bool) -> Result<()> {
+        loop {
+            match std::mem::replace(&mut self.state, PushState::Failed) {
+                PushState::Boundary(mut input) => {
+                    if input.len() == 0 {
+                        self.state = PushState::Boundary(input);
+                        return Ok(());
+                    }
+                    /* The header is parsed from a copy of the position, to start over if cut. */
+                    let mut header = input.fill_buf()?;
+                    match GzipReader::parse_header_counted(&mut header, HeaderEncoding::Latin1) {
+                        Ok((_, _, len)) => input.consume(len as usize),
+                        Err(DecodeError::UnexpectedEof { .. }) if wait => {
+                            self.state = PushState::Boundary(input);
+                            return Ok(());
+                        }
+                        Err(err) => return Err(err),
+                    }
+                    info!("parsed gzip header");
+                    self.state = PushState::Member {
+                        deflate_reader: Box::new(DeflateReader::new(BitReader::new(input))),
+                        writer: TrackingWriter::new(vec![]),
+                    };
+                }
+                PushState::Member {
+                    mut deflate_reader,
+                    mut writer,
+                } => {
+                    deflate_reader.set_wait_for_input(wait);
+                    let (position, byte_count) =
+                        (deflate_reader.bit_position(), writer.byte_count());
+                    let has_more = deflate_reader.decode_some(&mut writer, CHUNK_SIZE)?;
+                    output.append(writer.get_mut());
+                    if has_more {
+                        let stopped = deflate_reader.bit_position() == position
+                            && writer.byte_count() == byte_count;
+                        self.state = PushState::Member {
+                            deflate_reader,
+                            writer,
+                        };
+                        if stopped {
+                            return Ok(());
+                        }
+                    } else {
+                        self.state = PushState::Footer {
+                            input: deflate_reader.into_inner()?,
+                            byte_count: writer.byte_count(),
+                            crc: writer.current_crc32(),
+                        };
+                    }
+                }
+                PushState::Footer {
+                    mut input,
+                    byte_count,
+                    crc,
+                } => {
+                    if wait && input.len() < FOOTER_LEN as usize {
+                        self.state = PushState::Footer {
+                            input,
+                            byte_count,
+                            crc,
+                        };
+                        return Ok(());
+                    }
+                    MemberFooter::read(&mut input)?.verify(byte_count, Some(crc))?;
+                    self.state = PushState::Boundary(input);
+                }
+                PushState::Failed => return Err(already_failed()),
+            }
+        }
+    }
+
+    fn pending_input(&mut self) -> Option<&mut PendingInput> {
+        match &mut self.state {
+            PushState::Boundary(input) | PushState::Footer { input, .. } => Some(input),
+            PushState::Member { deflate_reader, .. } => Some(deflate_reader.get_mut()),
+            PushState::Failed => None,
+        }
+    }
+}
+
+impl Default for DecodeState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
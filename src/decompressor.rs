@@ -0,0 +1,159 @@
+#![forbid(unsafe_code)]
+
+use std::io::{BufRead, Write};
+
+use crate::error::Result;
+use crate::limited_writer::{unwrap_limit_error, LimitedWriter};
+use crate::progress_writer::ProgressWriter;
+
+////////////////////////////////////////////////////////////////////////////////
+
+const PROGRESS_INTERVAL: u64 = 64 * 1024;
+
+/// The options checked member by member, see the setters of `Decompressor`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct MemberOptions {
+    pub allow_trailing: bool,
+    pub max_members: usize,
+    pub verify_crc: bool,
+    pub verify_size: bool,
+    pub normalize_line_endings: bool,
+    /// Most bytes to skip looking for the first member, if any.
+    pub skip_to_magic: Option<u64>,
+    pub strict_padding: bool,
+    /// Number of distinct dynamic block codings to cache, if any.
+    pub tree_cache: Option<usize>,
+}
+
+impl Default for MemberOptions {
+    fn default() -> Self {
+        Self {
+            allow_trailing: false,
+            max_members: usize::MAX,
+            verify_crc: true,
+            verify_size: true,
+            normalize_line_endings: false,
+            skip_to_magic: None,
+            strict_padding: false,
+            tree_cache: None,
+        }
+    }
+}
+
+/// Gzip decompression with options, configured through chainable setters, e.g.
+/// `Decompressor::new().max_output(1 << 20).run(input, output)`.
+pub struct Decompressor<F = fn(u64)> {
+    max_output: u64,
+    options: MemberOptions,
+    on_progress: Option<F>,
+}
+
+impl Decompressor {
+    /// Same behavior as `decompress`: no limits, no trailing data, no progress reports.
+    pub fn new() -> Self {
+        Self {
+            max_output: u64::MAX,
+            options: MemberOptions::default(),
+            on_progress: None,
+        }
+    }
+}
+
+impl Default for Decompressor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F: FnMut(u64)> Decompressor<F> {
+    /// Fail as soon as the output of all members combined would exceed `max_bytes`.
+    pub fn max_output(self, max_bytes: u64) -> Self {
+        Self {
+            max_output: max_bytes,
+            ..self
+        }
+    }
+
+    /// Fail if the stream has more than `max_members` members.
+    pub fn max_members(mut self, max_members: usize) -> Self {
+        self.options.max_members = max_members;
+        self
+    }
+
+    /// Ignore data after the last member which can't be the start of another member,
+    /// leaving it unread in the input.
+    pub fn allow_trailing(mut self, allow_trailing: bool) -> Self {
+        self.options.allow_trailing = allow_trailing;
+        self
+    }
+
+    /// Whether to compute the CRC-32 of the output and check it against the footers.
+    /// Turning it off speeds up decoding of trusted data, ISIZE is still checked.
+    pub fn verify_crc(mut self, verify_crc: bool) -> Self {
+        self.options.verify_crc = verify_crc;
+        self
+    }
+
+    /// Whether a wrong ISIZE fails decompression. If not, it is only logged as long
+    /// as the CRC-32 of the member is right, for producers writing bogus sizes.
+    pub fn verify_size(mut self, verify_size: bool) -> Self {
+        self.options.verify_size = verify_size;
+        self
+    }
+
+    /// Turn CRLF line endings into LF in the members marked as text by FTEXT. Off by
+    /// default, so that the output is byte-exact.
+    pub fn normalize_line_endings(mut self, normalize: bool) -> Self {
+        self.options.normalize_line_endings = normalize;
+        self
+    }
+
+    /// Skip up to `max_skip` bytes before the first member, such as a self-extracting
+    /// stub, looking for the ID1, ID2 and CM of a deflate member.
+    pub fn skip_to_magic(mut self, max_skip: u64) -> Self {
+        self.options.skip_to_magic = Some(max_skip);
+        self
+    }
+
+    /// Reject members whose deflate stream ends with set padding bits rather than
+    /// zeros. Off by default, as some encoders leave garbage there.
+    pub fn strict_padding(mut self, strict: bool) -> Self {
+        self.options.strict_padding = strict;
+        self
+    }
+
+    /// Keep the Huffman codings of the last `capacity` distinct dynamic blocks of each
+    /// member, for streams whose encoder repeats the same trees from block to block.
+    pub fn tree_cache(mut self, capacity: usize) -> Self {
+        self.options.tree_cache = Some(capacity);
+        self
+    }
+
+    /// Call `callback` with the number of bytes decompressed so far after every 64 KiB
+    /// of output and once more when done. It isn't called again after an error.
+    pub fn on_progress<G: FnMut(u64)>(self, callback: G) -> Decompressor<G> {
+        Decompressor {
+            max_output: self.max_output,
+            options: self.options,
+            on_progress: Some(callback),
+        }
+    }
+
+    /// Decompress all the members in `input` into `output`.
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, output: W) -> Result<()> {
+        let output = LimitedWriter::new(output, self.max_output);
+        match &mut self.on_progress {
+            Some(callback) => {
+                let mut writer = ProgressWriter::new(output, PROGRESS_INTERVAL, callback);
+                crate::decompress_members(input, &mut writer, self.options)
+                    .map_err(unwrap_limit_error)?;
+                writer.finish();
+            }
+            None => {
+                crate::decompress_members(input, output, self.options)
+                    .map_err(unwrap_limit_error)?;
+            }
+        }
+        Ok(())
+    }
+}
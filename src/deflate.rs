@@ -1,13 +1,13 @@
 #![forbid(unsafe_code)]
 
-use std::io::{BufRead, Write};
+use alloc::vec::Vec;
 
-use anyhow::{anyhow, bail, ensure, Result};
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use log::*;
 
 use crate::bit_reader::BitReader;
-use crate::huffman_coding::{self, LitLenToken};
+use crate::error::{ensure, trace_symbol, DecodeError, EofContext, Result};
+use crate::huffman_coding::{self, DistanceToken, HuffmanCoding, LitLenToken, TreeCache};
+use crate::io::{BufRead, Write};
 use crate::tracking_writer::TrackingWriter;
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -18,36 +18,125 @@ pub struct BlockHeader {
     pub compression_type: CompressionType,
 }
 
-#[derive(Debug, PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd)]
 pub enum CompressionType {
+    #[default]
     Uncompressed = 0,
     FixedTree = 1,
     DynamicTree = 2,
     Reserved = 3,
 }
 
-impl Default for CompressionType {
-    fn default() -> Self {
-        Self::Uncompressed
+impl CompressionType {
+    /// All the block types but the reserved BTYPE 3.
+    pub fn is_supported(&self) -> bool {
+        *self != CompressionType::Reserved
     }
 }
 
+/// Converts the two BTYPE bits, wider values are a bug in the caller.
 impl From<u16> for CompressionType {
     fn from(num: u16) -> Self {
-        match num {
+        debug_assert!(num <= 3, "BTYPE is two bits wide, got {}", num);
+        match num & 0b11 {
             0 => CompressionType::Uncompressed,
             1 => CompressionType::FixedTree,
             2 => CompressionType::DynamicTree,
-            _ => CompressionType::Reserved,
+            3 => CompressionType::Reserved,
+            _ => unreachable!(),
         }
     }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
+/// Where a block starts and what its header says.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockInfo {
+    /// Offset of the BFINAL bit from the start of the deflate stream.
+    pub bit_offset: u64,
+    pub compression_type: CompressionType,
+    pub is_final: bool,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Statistics of a deflate stream decoded so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeflateStats {
+    pub stored_blocks: u64,
+    pub fixed_blocks: u64,
+    pub dynamic_blocks: u64,
+    /// Compressed bytes consumed from the input.
+    pub bytes_in: u64,
+    /// Decompressed bytes written to the output.
+    pub bytes_out: u64,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+const MAX_DISTANCE: u16 = 32768;
+const DEFLATE64_HISTORY_SIZE: usize = 65536;
+
+/// Most bits a block header takes: BFINAL, BTYPE, HLIT, HDIST and HCLEN, 19 code
+/// length codes, then up to 286 + 32 code lengths with their extra bits.
+const MAX_HEADER_BITS: u64 = 3 + 14 + 19 * 3 + (286 + 32) * (7 + 7);
+
+/// Most bits of a symbol with its extra bits: a DEFLATE64 length and a distance.
+const MAX_SYMBOL_BITS: u64 = 15 + 16 + 15 + 14;
+
+/// Most bits taken per byte of output by a symbol: a literal takes up to 15 bits,
+/// and a match of at least 3 bytes up to `MAX_SYMBOL_BITS`.
+const MAX_BITS_PER_BYTE: u64 = MAX_SYMBOL_BITS.div_ceil(3);
+
+/// Position inside the block being decoded.
+/* There is a single state per reader, so the codings are kept inline rather than boxed. */
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
+enum BlockState {
+    /// The next thing to read is a block header, unless the last block is done.
+    Header,
+    Stored {
+        remaining: usize,
+    },
+    Huffman {
+        litlen: HuffmanCoding<LitLenToken>,
+        dist: HuffmanCoding<DistanceToken>,
+        /// The codings are the fixed ones, to be put back into `fixed_codings`.
+        fixed: bool,
+    },
+}
+
+#[derive(Clone)]
 pub struct DeflateReader<T> {
     bit_reader: BitReader<T>,
     reached_last: bool,
+    state: BlockState,
+    /// Statistics, except for the bytes consumed by `bit_reader` itself.
+    stats: DeflateStats,
+    /// Preset dictionary, copied into the history window once decoding starts.
+    dictionary: Vec<u8>,
+    /// Whether the history window of the writer is prepared for this stream.
+    started: bool,
+    /// Accept the DEFLATE64 extensions: longer matches and a 64 KiB window.
+    deflate64: bool,
+    /// Back-references further than this are rejected.
+    window_size: usize,
+    /// Headers of the blocks read so far, if asked for.
+    blocks: Option<Vec<BlockInfo>>,
+    /// Reject set bits padding the final block.
+    strict_padding: bool,
+    /// Keep the codings of finished Huffman blocks in `spare_codings`.
+    reuse_tables: bool,
+    /// Codings of a finished block, rebuilt in place for the next dynamic block.
+    spare_codings: Option<(HuffmanCoding<LitLenToken>, HuffmanCoding<DistanceToken>)>,
+    /// Codings of recent dynamic blocks, if asked for.
+    tree_cache: Option<TreeCache>,
+    /// The fixed codings, built by the first fixed block and then kept between them.
+    fixed_codings: Option<(HuffmanCoding<LitLenToken>, HuffmanCoding<DistanceToken>)>,
+    /// Stop before a step which may need more input than is buffered, see
+    /// `set_wait_for_input`.
+    wait_for_input: bool,
 }
 
 impl<T: BufRead> DeflateReader<T> {
@@ -55,6 +144,125 @@ impl<T: BufRead> DeflateReader<T> {
         Self {
             bit_reader,
             reached_last: false,
+            state: BlockState::Header,
+            stats: DeflateStats::default(),
+            dictionary: Vec::new(),
+            started: false,
+            deflate64: false,
+            window_size: MAX_DISTANCE.into(),
+            blocks: None,
+            strict_padding: false,
+            reuse_tables: false,
+            spare_codings: None,
+            tree_cache: None,
+            fixed_codings: None,
+            wait_for_input: false,
+        }
+    }
+
+    /// Same as `new`, but decodes DEFLATE64: code 285 stands for lengths of 3 to
+    /// 65538 bytes and distance codes 30 and 31 reach up to 64 KiB back.
+    pub fn new_deflate64(bit_reader: BitReader<T>) -> Self {
+        Self {
+            deflate64: true,
+            window_size: DEFLATE64_HISTORY_SIZE,
+            ..Self::new(bit_reader)
+        }
+    }
+
+    /// Reject back-references further than `window_size` bytes, a power of two from
+    /// 256 up to the window of the format. Writers created by the reader itself
+    /// allocate no more than that.
+    pub fn window_size(mut self, window_size: usize) -> Self {
+        assert!(
+            window_size.is_power_of_two() && (256..=self.window_size).contains(&window_size),
+            "invalid window size {}",
+            window_size
+        );
+        self.window_size = window_size;
+        self
+    }
+
+    /// Rebuild the Huffman codings of dynamic blocks in the buffers of the previous
+    /// block rather than allocating new ones, which helps streams with many blocks.
+    /// The members of a gzip stream are always decoded this way.
+    pub fn with_reused_tables(mut self) -> Self {
+        self.reuse_tables = true;
+        self
+    }
+
+    /// Whether the bits padding the final block to a byte boundary must be zero, as
+    /// well-formed encoders write them. Off by default.
+    pub fn strict_padding(mut self, strict: bool) -> Self {
+        self.strict_padding = strict;
+        self
+    }
+
+    /// Keep the codings of the last `capacity` distinct dynamic blocks, so that blocks
+    /// repeating the code lengths of one of them don't build their codings again.
+    pub fn with_tree_cache(mut self, capacity: usize) -> Self {
+        self.tree_cache = Some(TreeCache::new(capacity));
+        self
+    }
+
+    /// Keep the position and header of every block, see `blocks`.
+    pub fn record_blocks(mut self) -> Self {
+        self.blocks = Some(Vec::new());
+        self
+    }
+
+    /// Same as `new`, but back-references may point into `dictionary` as if it
+    /// had been output right before the stream.
+    pub fn with_dictionary(bit_reader: BitReader<T>, dictionary: &[u8]) -> Self {
+        Self {
+            dictionary: dictionary.to_vec(),
+            ..Self::new(bit_reader)
+        }
+    }
+
+    /// Start over with the stream in `bit_reader`, keeping the settings and the preset
+    /// dictionary. The writer passed afterwards should be new or reset as well.
+    pub fn reset(&mut self, bit_reader: BitReader<T>) {
+        self.bit_reader = bit_reader;
+        self.reached_last = false;
+        self.state = BlockState::Header;
+        self.stats = DeflateStats::default();
+        self.started = false;
+        if let Some(blocks) = &mut self.blocks {
+            blocks.clear();
+        }
+    }
+
+    /// Return the underlying reader, positioned at the byte boundary after the
+    /// last consumed bit. The remaining bits of that byte, which pad the final
+    /// block, are discarded.
+    pub fn into_inner(self) -> Result<T> {
+        Ok(self.bit_reader.into_inner()?)
+    }
+
+    /// Rather than fail at the end of the input, stop decoding before a block header or
+    /// a symbol which may not be entirely in the buffer of the underlying reader, so that
+    /// decoding can go on once more input is appended to it. Decoding then stops
+    /// with up to a few hundred bytes of input left.
+    pub(crate) fn set_wait_for_input(&mut self, wait: bool) {
+        self.wait_for_input = wait;
+    }
+
+    /// Mutable reference to the underlying reader, see `BitReader::get_mut`.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.bit_reader.get_mut()
+    }
+
+    /// Headers of the blocks read so far, including a rejected one, in stream order.
+    /// Empty unless `record_blocks` was called.
+    pub fn blocks(&self) -> &[BlockInfo] {
+        self.blocks.as_deref().unwrap_or_default()
+    }
+
+    pub fn stats(&self) -> DeflateStats {
+        DeflateStats {
+            bytes_in: self.stats.bytes_in + self.bit_reader.bytes_consumed(),
+            ..self.stats
         }
     }
 
@@ -68,90 +276,835 @@ impl<T: BufRead> DeflateReader<T> {
                 header.is_final = is_final.bits() == 1;
                 self.reached_last |= header.is_final
             }
-            Err(err) => return Some(Err(anyhow!(err))),
+            Err(err) => return Some(Err(err.into())),
         }
         match self.bit_reader.read_bits(2) {
             Ok(comp_type) => {
                 header.compression_type = comp_type.bits().into();
             }
-            Err(err) => return Some(Err(anyhow!(err))),
+            Err(err) => return Some(Err(err.into())),
         }
         Some(Ok((header, &mut self.bit_reader)))
     }
 
-    pub fn deflate<W: Write>(&mut self, output: W) -> Result<(u32, (u32, W))> {
-        let mut writer = TrackingWriter::<W>::new(output);
+    pub fn deflate<W: Write>(&mut self, mut output: W) -> Result<(u64, (u32, W))> {
+        let (byte_count, crc32) = self.deflate_into(&mut output)?;
+        Ok((byte_count, (crc32, output)))
+    }
+
+    /// Same as `deflate`, but borrows `output`, e.g. to decode several streams into
+    /// it. Returns the number of bytes written and their CRC-32.
+    pub fn deflate_into<W: Write>(&mut self, output: &mut W) -> Result<(u64, u32)> {
+        let mut writer =
+            TrackingWriter::with_window_size(output, self.window_size.min(MAX_DISTANCE.into()));
+        self.deflate_tracked(&mut writer)?;
+        Ok((writer.byte_count(), writer.current_crc32()))
+    }
 
-        while let Some(result) = self.next_block() {
-            match result {
-                Ok((block_header, bit_reader)) => {
-                    info!("processing block");
-                    debug!("ISFINAL:\t{:?}", block_header.is_final);
-                    debug!("BTYPE:\t{:?}", block_header.compression_type);
-                    if block_header.compression_type == CompressionType::Reserved {
-                        bail!("unsupported block type");
-                    }
+    /// Decodes the remaining blocks into `writer` and leaves the underlying
+    /// stream right after the last block.
+    pub(crate) fn deflate_tracked<W: Write>(
+        &mut self,
+        writer: &mut TrackingWriter<W>,
+    ) -> Result<()> {
+        while self.decode_some(writer, usize::MAX)? {}
+        Ok(())
+    }
 
-                    if block_header.compression_type == CompressionType::Uncompressed {
-                        let reader = bit_reader.borrow_reader_from_boundary();
-                        let len = reader.read_u16::<LittleEndian>()?;
-                        let nlen = reader.read_u16::<LittleEndian>()?;
-                        ensure!(len == !nlen, "nlen check failed");
-                        debug!("copying {} bytes", len);
-                        let mut buffer = vec![0; len.into()];
-                        reader.read_exact(&mut buffer)?;
-                        writer.write_all(&buffer)?;
-                        continue;
-                    }
+    /// Decode until at least `min_len` more bytes are written or the stream ends.
+    /// Returns `false` once the last block is done, leaving the underlying stream
+    /// right after it.
+    pub(crate) fn decode_some<W: Write>(
+        &mut self,
+        writer: &mut TrackingWriter<W>,
+        min_len: usize,
+    ) -> Result<bool> {
+        let start = writer.byte_count();
+        let result = self.decode_blocks(writer, min_len, false);
+        self.stats.bytes_out += writer.byte_count() - start;
+        result
+    }
 
-                    info!("decoding trees");
-                    let (litlen, dist) = match block_header.compression_type {
-                        CompressionType::DynamicTree => {
-                            huffman_coding::decode_litlen_distance_trees(bit_reader)?
+    /// Decode up to the end of the first block to output anything, stopping right
+    /// before the header of the next block. Returns `false` once the last block is
+    /// done, leaving the underlying stream right after it.
+    pub(crate) fn decode_block<W: Write>(
+        &mut self,
+        writer: &mut TrackingWriter<W>,
+    ) -> Result<bool> {
+        let start = writer.byte_count();
+        let result = self.decode_blocks(writer, usize::MAX, true);
+        self.stats.bytes_out += writer.byte_count() - start;
+        result
+    }
+
+    /// Number of bits of the stream consumed so far, stored blocks included. Once the
+    /// last block is done, it is where the stream ends, before the padding bits.
+    pub fn bit_position(&self) -> u64 {
+        self.bit_reader.bit_position() + self.stats.bytes_in * 8
+    }
+
+    fn decode_blocks<W: Write>(
+        &mut self,
+        writer: &mut TrackingWriter<W>,
+        min_len: usize,
+        until_block_end: bool,
+    ) -> Result<bool> {
+        if !self.started {
+            writer.grow_history(self.window_size);
+            writer.preload_history(&self.dictionary);
+            self.started = true;
+        }
+
+        let start = writer.byte_count();
+        let target = start.saturating_add(min_len as u64);
+        while writer.byte_count() < target {
+            match &mut self.state {
+                BlockState::Header => {
+                    if until_block_end && writer.byte_count() > start {
+                        return Ok(true);
+                    }
+                    if self.wait_for_input
+                        && !self.reached_last
+                        && self.bit_reader.buffered_bits()? < MAX_HEADER_BITS
+                    {
+                        return Ok(true);
+                    }
+                    if !self.read_block_header()? {
+                        writer.flush()?;
+                        if self.strict_padding {
+                            let padding = self.bit_reader.take_partial_byte().bits();
+                            ensure!(padding == 0, DecodeError::NonZeroPadding(padding as u8));
+                        }
+                        self.bit_reader.borrow_reader_from_boundary()?;
+                        return Ok(false);
+                    }
+                }
+                BlockState::Stored { remaining } => {
+                    let reader = self.bit_reader.borrow_reader_from_boundary()?;
+                    let buf = reader.fill_buf()?;
+                    if buf.is_empty() && self.wait_for_input {
+                        return Ok(true);
+                    }
+                    ensure!(
+                        !buf.is_empty(),
+                        DecodeError::UnexpectedEof {
+                            context: EofContext::Body
                         }
-                        CompressionType::FixedTree => huffman_coding::get_fixed_coding()?,
-                        _ => bail!("bad compression type"),
-                    };
-
-                    info!("processing symbols");
-                    loop {
-                        let symbol = litlen.read_symbol(bit_reader)?;
-                        debug!("symbol: {:?}", symbol);
-                        match symbol {
-                            LitLenToken::Literal(lit) => writer.write_u8(lit)?,
-                            LitLenToken::Length { base, extra_bits } => {
-                                let extra_len = if extra_bits != 0 {
-                                    bit_reader.read_bits(extra_bits)?.bits()
-                                } else {
-                                    0
-                                };
-                                let actual_len: usize = (base + extra_len).into();
-
-                                let dist = dist.read_symbol(bit_reader)?;
-                                let extra_dist = if dist.extra_bits != 0 {
-                                    bit_reader.read_bits(dist.extra_bits)?.bits()
-                                } else {
-                                    0
-                                };
-                                let actual_dist: usize = (dist.base + extra_dist).into();
-
-                                debug!("dist: {}, len: {}", actual_dist, actual_len);
-
-                                writer.write_previous(actual_dist, actual_len)?;
-                            }
-                            LitLenToken::EndOfBlock => {
-                                info!("reached end of block");
-                                break;
-                            }
+                    );
+                    let wanted =
+                        usize::try_from(target - writer.byte_count()).unwrap_or(usize::MAX);
+                    let len = buf.len().min(*remaining).min(wanted);
+                    writer.write_all(&buf[..len])?;
+                    reader.consume(len);
+                    self.stats.bytes_in += len as u64;
+                    *remaining -= len;
+                    if *remaining == 0 {
+                        self.state = BlockState::Header;
+                    }
+                }
+                BlockState::Huffman { .. } => {
+                    let mut target = target;
+                    if self.wait_for_input {
+                        /* Symbols are decoded up to an output size their input can't exceed. */
+                        let bits = self.bit_reader.buffered_bits()?;
+                        let max_len = bits.saturating_sub(2 * MAX_SYMBOL_BITS) / MAX_BITS_PER_BYTE;
+                        if max_len == 0 {
+                            return Ok(true);
                         }
+                        target = target.min(writer.byte_count() + max_len);
                     }
+                    let mut literals = LiteralRun::new();
+                    let result = self.decode_symbols(writer, target, &mut literals);
+                    /* Literals decoded before an error are output all the same. */
+                    literals.flush(writer)?;
+                    result?;
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Decode symbols of the current Huffman block until `target` bytes are written
+    /// or the block ends. Literals are collected into `literals` and written in runs.
+    fn decode_symbols<W: Write>(
+        &mut self,
+        writer: &mut TrackingWriter<W>,
+        target: u64,
+        literals: &mut LiteralRun,
+    ) -> Result<()> {
+        let BlockState::Huffman { litlen, dist, .. } = &mut self.state else {
+            return Ok(());
+        };
+        let bit_reader = &mut self.bit_reader;
+        while writer.byte_count() + (literals.len as u64) < target {
+            let symbol = litlen.read_symbol(bit_reader)?;
+            trace_symbol!("symbol: {:?}", symbol);
+            let (base, extra_bits) = match symbol {
+                LitLenToken::Literal(lit) => {
+                    literals.push(lit, writer)?;
+                    continue;
                 }
-                Err(err) => bail!(err),
+                LitLenToken::EndOfBlock => {
+                    info!("reached end of block");
+                    self.finish_huffman_block();
+                    break;
+                }
+                LitLenToken::Length { base, extra_bits } => (base, extra_bits),
+                LitLenToken::MaxLength if self.deflate64 => (3, 16),
+                LitLenToken::MaxLength => (258, 0),
+            };
+            let extra_len = bit_reader.read_bits_u32(extra_bits)?;
+            let actual_len = usize::from(base) + extra_len as usize;
+
+            let dist = match dist.read_symbol(bit_reader) {
+                Ok(dist) => dist,
+                Err(_) if dist.is_empty() => return Err(DecodeError::NoDistanceCodes),
+                Err(err) => return Err(err),
+            };
+            let extra_dist = bit_reader.read_bits_u32(dist.extra_bits)?;
+            let actual_dist = usize::from(dist.base) + extra_dist as usize;
+            /* Only distance codes 30 and 31, which never occur in DEFLATE, go further. */
+            ensure!(
+                self.deflate64 || actual_dist <= MAX_DISTANCE.into(),
+                DecodeError::DistanceTooLarge {
+                    distance: actual_dist
+                }
+            );
+            ensure!(
+                actual_dist <= self.window_size,
+                DecodeError::DistanceTooFar {
+                    distance: actual_dist,
+                    window_size: self.window_size,
+                }
+            );
+
+            trace_symbol!("dist: {}, len: {}", actual_dist, actual_len);
+
+            /* The match may refer to the pending literals. */
+            literals.flush(writer)?;
+            writer.write_previous(actual_dist, actual_len)?;
+        }
+        Ok(())
+    }
+
+    /// Move on to the next block header, keeping the fixed codings, and the dynamic
+    /// ones if they are reused.
+    fn finish_huffman_block(&mut self) {
+        match core::mem::replace(&mut self.state, BlockState::Header) {
+            BlockState::Huffman {
+                litlen,
+                dist,
+                fixed: true,
+            } => self.fixed_codings = Some((litlen, dist)),
+            BlockState::Huffman { litlen, dist, .. } if self.reuse_tables => {
+                self.spare_codings = Some((litlen, dist))
+            }
+            _ => {}
+        }
+    }
+
+    /// Start the next block. Returns `false` if the last block is already done.
+    fn read_block_header(&mut self) -> Result<bool> {
+        /* Stored block contents are read past the bit reader, but counted in the stats. */
+        let bit_offset = self.bit_position();
+        debug!("block header at bit {}", bit_offset);
+        let block_header = match self.next_block() {
+            Some(result) => result?.0,
+            None => return Ok(false),
+        };
+        info!("processing block");
+        debug!("ISFINAL:\t{:?}", block_header.is_final);
+        debug!("BTYPE:\t{:?}", block_header.compression_type);
+        if let Some(blocks) = &mut self.blocks {
+            blocks.push(BlockInfo {
+                bit_offset,
+                compression_type: block_header.compression_type,
+                is_final: block_header.is_final,
+            });
+        }
+        ensure!(
+            block_header.compression_type.is_supported(),
+            DecodeError::ReservedBlockType {
+                block_index: self.stats.stored_blocks
+                    + self.stats.fixed_blocks
+                    + self.stats.dynamic_blocks,
+                bit_offset,
+            }
+        );
+
+        let bit_reader = &mut self.bit_reader;
+        self.state = match block_header.compression_type {
+            CompressionType::Uncompressed => {
+                bit_reader.align_to_byte();
+                let len = bit_reader.read_bits_u32(16)? as u16;
+                let nlen = bit_reader.read_bits_u32(16)? as u16;
+                ensure!(
+                    len == !nlen,
+                    DecodeError::StoredBlockLengthMismatch { len, nlen }
+                );
+                self.stats.stored_blocks += 1;
+                debug!("copying {} bytes", len);
+                match len {
+                    0 => BlockState::Header,
+                    len => BlockState::Stored {
+                        remaining: len.into(),
+                    },
+                }
+            }
+            CompressionType::FixedTree => {
+                info!("decoding trees");
+                let (litlen, dist) = match self.fixed_codings.take() {
+                    Some(codings) => codings,
+                    None => huffman_coding::get_fixed_coding()?,
+                };
+                self.stats.fixed_blocks += 1;
+                BlockState::Huffman {
+                    litlen,
+                    dist,
+                    fixed: true,
+                }
+            }
+            CompressionType::DynamicTree => {
+                info!("decoding trees");
+                let (litlen, dist) = match (&mut self.tree_cache, self.spare_codings.take()) {
+                    (Some(cache), _) => huffman_coding::decode_litlen_distance_trees_cached(
+                        bit_reader,
+                        self.deflate64,
+                        cache,
+                    )?,
+                    (None, Some((mut litlen, mut dist))) => {
+                        huffman_coding::decode_litlen_distance_trees_into(
+                            bit_reader,
+                            self.deflate64,
+                            &mut litlen,
+                            &mut dist,
+                        )?;
+                        (litlen, dist)
+                    }
+                    (None, None) => {
+                        huffman_coding::decode_litlen_distance_trees(bit_reader, self.deflate64)?
+                    }
+                };
+                self.stats.dynamic_blocks += 1;
+                BlockState::Huffman {
+                    litlen,
+                    dist,
+                    fixed: false,
+                }
+            }
+            CompressionType::Reserved => unreachable!("rejected above"),
+        };
+        Ok(true)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+const LITERAL_RUN_SIZE: usize = 256;
+
+/// Consecutive literals, written with a single call instead of one call per byte.
+struct LiteralRun {
+    buf: [u8; LITERAL_RUN_SIZE],
+    len: usize,
+}
+
+impl LiteralRun {
+    fn new() -> Self {
+        Self {
+            buf: [0; LITERAL_RUN_SIZE],
+            len: 0,
+        }
+    }
+
+    fn push<W: Write>(&mut self, lit: u8, writer: &mut TrackingWriter<W>) -> Result<()> {
+        self.buf[self.len] = lit;
+        self.len += 1;
+        if self.len == LITERAL_RUN_SIZE {
+            self.flush(writer)?;
+        }
+        Ok(())
+    }
+
+    fn flush<W: Write>(&mut self, writer: &mut TrackingWriter<W>) -> Result<()> {
+        let len = core::mem::take(&mut self.len);
+        writer.write_all(&self.buf[..len])?;
+        Ok(())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_reader::BitSequence;
+    use crate::bit_writer::BitWriter;
+    use crate::huffman_coding::{fixed_distance_code, fixed_litlen_code};
+
+    const DICTIONARY: &[u8] = b"[package]\nname = \"ripgzip\"\n";
+    const DATA: &[u8] = b"[package]\nname = \"ripgzip\"\nversion = \"0.1.0\"\n";
+
+    #[test]
+    fn single_distance_code() -> Result<()> {
+        /* A dynamic block coding "aaaaaaaaaa" with a lone one-bit distance code. */
+        let compressed: &[u8] = &[61, 192, 33, 1, 0, 0, 0, 128, 160, 173, 252, 63, 97, 19, 11];
+        let mut reader = DeflateReader::new(BitReader::new(compressed));
+        let (_, (_, output)) = reader.deflate(vec![])?;
+        assert_eq!(output, b"aaaaaaaaaa");
+        Ok(())
+    }
+
+    #[test]
+    fn with_dictionary() -> Result<()> {
+        let compressed: &[u8] = &[
+            139, 198, 45, 85, 150, 90, 84, 156, 153, 159, 7, 18, 51, 208, 51, 212, 51, 80, 226, 2,
+            0,
+        ];
+
+        let mut reader = DeflateReader::with_dictionary(BitReader::new(compressed), DICTIONARY);
+        let (byte_count, (crc, output)) = reader.deflate(vec![])?;
+        assert_eq!(output, DATA);
+        assert_eq!(byte_count, DATA.len() as u64);
+        assert_eq!(
+            crc,
+            crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC).checksum(DATA)
+        );
+
+        let mut reader = DeflateReader::new(BitReader::new(compressed));
+        let err = reader.deflate(vec![]).unwrap_err();
+        assert!(matches!(
+            err,
+            DecodeError::DistanceBeforeStart {
+                distance: 27,
+                available: 1
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "distance 27 reaches before the start of the data, 1 bytes are available"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn literals_before_error() -> Result<()> {
+        /* 300 literals, then a match with the distance code 30, invalid in DEFLATE. */
+        let mut writer = BitWriter::new(vec![]);
+        writer.write_bits(0b011, 3)?;
+        for i in 0..300 {
+            writer.write_code(fixed_litlen_code(b'a' as u16 + i % 26))?;
+        }
+        writer.write_code(fixed_litlen_code(257))?;
+        writer.write_code(fixed_distance_code(30))?;
+        writer.write_bits(0, 14)?;
+        let compressed = writer.into_inner()?;
+
+        let mut output = vec![];
+        let mut tracking_writer = TrackingWriter::new(&mut output);
+        let mut reader = DeflateReader::new(BitReader::new(compressed.as_slice()));
+        assert!(matches!(
+            reader.deflate_tracked(&mut tracking_writer),
+            Err(DecodeError::DistanceTooLarge { distance: 32769 })
+        ));
+        assert_eq!(tracking_writer.byte_count(), 300);
+        assert_eq!(output.len(), 300);
+        assert!(output.starts_with(b"abcdefghijklmnopqrstuvwxyzabc"));
+        Ok(())
+    }
+
+    #[test]
+    fn stored_block_history() -> Result<()> {
+        let data: Vec<u8> = (0..65535u32).map(|i| (i * 31 % 256) as u8).collect();
+
+        /* A full stored block, then a match reaching 32768 bytes back into it. */
+        let mut writer = BitWriter::new(vec![]);
+        writer.write_bits(0b000, 3)?;
+        let stream = writer.borrow_writer_from_boundary()?;
+        stream.write_all(&[0xff, 0xff, 0, 0])?;
+        stream.write_all(&data)?;
+        writer.write_bits(0b011, 3)?;
+        writer.write_code(fixed_litlen_code(285))?;
+        writer.write_code(fixed_distance_code(29))?;
+        writer.write_bits(32768 - 24577, 13)?;
+        writer.write_code(fixed_litlen_code(256))?;
+        let compressed = writer.into_inner()?;
+
+        let mut expected = data.clone();
+        expected.extend_from_within(data.len() - 32768..data.len() - 32768 + 258);
+        let expected_crc = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC).checksum(&expected);
+        for capacity in [1, 1000, 8192, 1 << 20] {
+            let input = std::io::BufReader::with_capacity(capacity, compressed.as_slice());
+            let mut reader = DeflateReader::new(BitReader::new(input));
+            let (byte_count, (crc, output)) = reader.deflate(vec![])?;
+            assert_eq!(output, expected);
+            assert_eq!(byte_count, expected.len() as u64);
+            assert_eq!(crc, expected_crc);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn compression_type() {
+        assert_eq!(CompressionType::from(0), CompressionType::Uncompressed);
+        assert_eq!(CompressionType::from(2), CompressionType::DynamicTree);
+        assert_eq!(CompressionType::from(3), CompressionType::Reserved);
+        assert!(CompressionType::FixedTree.is_supported());
+        assert!(!CompressionType::Reserved.is_supported());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "BTYPE is two bits wide")]
+    fn compression_type_too_wide() {
+        let _ = CompressionType::from(4);
+    }
+
+    #[test]
+    fn reserved_block_type() {
+        /* An empty stored block, then a final block with BTYPE 3. */
+        let compressed: &[u8] = &[0b000, 0, 0, !0, !0, 0b111];
+        let mut reader = DeflateReader::new(BitReader::new(compressed)).record_blocks();
+        let err = reader.deflate(vec![]).unwrap_err();
+        assert!(matches!(
+            err,
+            DecodeError::ReservedBlockType {
+                block_index: 1,
+                bit_offset: 40
+            }
+        ));
+        assert_eq!(
+            reader.blocks()[1],
+            BlockInfo {
+                bit_offset: 40,
+                compression_type: CompressionType::Reserved,
+                is_final: true,
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "unsupported block type in block 1 at bit 40"
+        );
+    }
+
+    #[test]
+    fn stored_block_length_mismatch() -> Result<()> {
+        let compressed: &[u8] = &[0b001, 3, 0, !3, !0, b'a', b'b', b'c'];
+        let mut reader = DeflateReader::new(BitReader::new(compressed));
+        assert_eq!(reader.deflate(vec![])?.1 .1, b"abc");
+
+        let mut corrupted = compressed.to_vec();
+        corrupted[4] ^= 0b100;
+        let mut reader = DeflateReader::new(BitReader::new(corrupted.as_slice()));
+        let err = reader.deflate(vec![]).unwrap_err();
+        assert!(matches!(
+            err,
+            DecodeError::StoredBlockLengthMismatch {
+                len: 3,
+                nlen: 0xfbfc
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "nlen check failed: len is 0x0003, nlen is 0xfbfc"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reset() -> Result<()> {
+        let first: &[u8] = &[0b001, 3, 0, !3, !0, b'a', b'b', b'c'];
+        let second: &[u8] = &[
+            139, 198, 45, 85, 150, 90, 84, 156, 153, 159, 7, 18, 51, 208, 51, 212, 51, 80, 226, 2,
+            0,
+        ];
+
+        let mut reader = DeflateReader::with_dictionary(BitReader::new(first), DICTIONARY);
+        let mut writer = TrackingWriter::new(vec![]);
+        reader.deflate_tracked(&mut writer)?;
+        assert_eq!(writer.get_mut(), b"abc");
+
+        reader.reset(BitReader::new(second));
+        writer.reset();
+        writer.get_mut().clear();
+        reader.deflate_tracked(&mut writer)?;
+
+        let mut fresh = DeflateReader::with_dictionary(BitReader::new(second), DICTIONARY);
+        let (byte_count, (crc, output)) = fresh.deflate(vec![])?;
+        assert_eq!(writer.get_mut(), &output);
+        assert_eq!(writer.byte_count(), byte_count);
+        assert_eq!(writer.current_crc32(), crc);
+        assert_eq!(reader.stats(), fresh.stats());
+        Ok(())
+    }
+
+    #[test]
+    fn deflate_into() -> Result<()> {
+        let crc = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+        let first: &[u8] = &[0b001, 3, 0, !3, !0, b'a', b'b', b'c'];
+        let second: &[u8] = &[0b001, 2, 0, !2, !0, b'd', b'e'];
+
+        let mut output = vec![];
+        let mut reader = DeflateReader::new(BitReader::new(first));
+        assert_eq!(reader.deflate_into(&mut output)?, (3, crc.checksum(b"abc")));
+        reader.reset(BitReader::new(second));
+        assert_eq!(reader.deflate_into(&mut output)?, (2, crc.checksum(b"de")));
+        assert_eq!(output, b"abcde");
+        Ok(())
+    }
+
+    /// Write the dynamic block of `single_distance_code`, which codes "aaaaaaaaaa".
+    fn write_single_distance_block(writer: &mut BitWriter<Vec<u8>>, is_final: bool) -> Result<()> {
+        /* The 116 bits of the block after BFINAL. */
+        let block: &[u8] = &[61, 192, 33, 1, 0, 0, 0, 128, 160, 173, 252, 63, 97, 19, 11];
+        writer.write_bits(is_final.into(), 1)?;
+        let mut reader = BitReader::new(block);
+        reader.read_bits(1)?;
+        for _ in 0..116 {
+            writer.write_bits(reader.read_bits_u32(1)?, 1)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reused_tables() -> Result<()> {
+        const BLOCK_COUNT: usize = 3000;
+
+        let mut writer = BitWriter::new(vec![]);
+        for i in 0..BLOCK_COUNT {
+            write_single_distance_block(&mut writer, i + 1 == BLOCK_COUNT)?;
+        }
+        let compressed = writer.into_inner()?;
+
+        let mut reader = DeflateReader::new(BitReader::new(&compressed[..])).with_reused_tables();
+        let (_, (_, output)) = reader.deflate(vec![])?;
+        assert_eq!(output, b"aaaaaaaaaa".repeat(BLOCK_COUNT));
+        assert_eq!(reader.stats().dynamic_blocks, BLOCK_COUNT as u64);
+        Ok(())
+    }
+
+    #[test]
+    fn strict_padding() -> Result<()> {
+        /* The stream of `single_distance_code` with the last of its 3 padding bits set. */
+        let compressed: &[u8] = &[61, 192, 33, 1, 0, 0, 0, 128, 160, 173, 252, 63, 97, 19, 139];
+        let mut reader = DeflateReader::new(BitReader::new(compressed));
+        assert_eq!(reader.deflate(vec![])?.1 .1, b"aaaaaaaaaa");
+
+        let mut reader = DeflateReader::new(BitReader::new(compressed)).strict_padding(true);
+        assert!(matches!(
+            reader.deflate(vec![]),
+            Err(DecodeError::NonZeroPadding(0b100))
+        ));
+
+        let compressed = &[&compressed[..14], &[11]].concat();
+        let mut reader = DeflateReader::new(BitReader::new(&compressed[..])).strict_padding(true);
+        assert_eq!(reader.deflate(vec![])?.1 .1, b"aaaaaaaaaa");
+        Ok(())
+    }
+
+    /// A final dynamic block with the literal/length code lengths `lengths`, by symbol,
+    /// and a single zero distance code length, followed by `codes`.
+    fn literal_only_block(lengths: &[(u16, u8)], codes: &[BitSequence]) -> Result<Vec<u8>> {
+        let mut writer = BitWriter::new(vec![]);
+        write_literal_only_block(&mut writer, true, lengths, codes)?;
+        Ok(writer.into_inner()?)
+    }
+
+    fn write_literal_only_block(
+        writer: &mut BitWriter<Vec<u8>>,
+        is_final: bool,
+        lengths: &[(u16, u8)],
+        codes: &[BitSequence],
+    ) -> Result<()> {
+        let hlit = lengths.iter().map(|&(symbol, _)| symbol + 1).max().unwrap();
+        let mut code_lengths = vec![0; usize::from(hlit) + 1];
+        for &(symbol, len) in lengths {
+            code_lengths[usize::from(symbol)] = len;
+        }
+
+        writer.write_bits(is_final.into(), 1)?;
+        writer.write_bits(CompressionType::DynamicTree as u32, 2)?;
+        writer.write_bits(u32::from(hlit - 257), 5)?;
+        writer.write_bits(0, 5)?;
+        /* Two-bit codes 00, 01, 10 and 11 for code lengths 0, 1, 2 and 18. */
+        writer.write_bits(14, 4)?;
+        for len in [0, 0, 2, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 2] {
+            writer.write_bits(len, 3)?;
+        }
+        let mut pos = 0;
+        while pos < code_lengths.len() {
+            let zeros = code_lengths[pos..]
+                .iter()
+                .take_while(|&&len| len == 0)
+                .count();
+            if zeros >= 11 {
+                let run = zeros.min(138);
+                writer.write_code(BitSequence::new(0b11, 2))?;
+                writer.write_bits(run as u32 - 11, 7)?;
+                pos += run;
+            } else {
+                writer.write_code(BitSequence::new(code_lengths[pos].into(), 2))?;
+                pos += 1;
+            }
+        }
+        for &code in codes {
+            writer.write_code(code)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn no_distance_codes() -> Result<()> {
+        /* Codes 0 for 'a', 10 for 'b' and 11 for the end of the block. */
+        let (a, b, eob) = (
+            BitSequence::new(0b0, 1),
+            BitSequence::new(0b10, 2),
+            BitSequence::new(0b11, 2),
+        );
+        let compressed = literal_only_block(&[(97, 1), (98, 2), (256, 2)], &[a, b, b, a, eob])?;
+        let mut reader = DeflateReader::new(BitReader::new(&compressed[..]));
+        assert_eq!(reader.deflate(vec![])?.1 .1, b"abba");
+        assert_eq!(reader.stats().dynamic_blocks, 1);
+
+        /* Code 11 is now the length 3, which has no distance to go with it. */
+        let compressed = literal_only_block(&[(97, 1), (256, 2), (257, 2)], &[a, a, eob])?;
+        let err = DeflateReader::new(BitReader::new(&compressed[..]))
+            .deflate(vec![])
+            .unwrap_err();
+        assert!(matches!(err, DecodeError::NoDistanceCodes));
+        assert_eq!(
+            err.to_string(),
+            "back-reference in a block without distance codes"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn fixed_codings_built_once() -> Result<()> {
+        /* Fixed blocks holding a single "a" each, with a stored block among them. */
+        let mut writer = BitWriter::new(vec![]);
+        for i in 0..100 {
+            if i == 50 {
+                writer.write_bits(0, 3)?;
+                writer
+                    .borrow_writer_from_boundary()?
+                    .extend([0, 0, 0xff, 0xff]);
             }
+            writer.write_bits((i == 99).into(), 1)?;
+            writer.write_bits(CompressionType::FixedTree as u32, 2)?;
+            writer.write_code(fixed_litlen_code(u16::from(b'a')))?;
+            writer.write_code(fixed_litlen_code(256))?;
+        }
+        let compressed = writer.into_inner()?;
+
+        let mut reader = DeflateReader::new(BitReader::new(&compressed[..]));
+        let (_, (_, output)) = reader.deflate(vec![])?;
+        assert_eq!(output, [b'a'; 100]);
+        assert_eq!(reader.stats().fixed_blocks, 100);
+        assert_eq!(
+            huffman_coding::FIXED_CODING_BUILDS.with(|builds| builds.get()),
+            1
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn tree_cache() -> Result<()> {
+        /* Codes 0 for 'a', 10 for 'b' and 11 for the end of the block. */
+        let (a, b, eob) = (
+            BitSequence::new(0b0, 1),
+            BitSequence::new(0b10, 2),
+            BitSequence::new(0b11, 2),
+        );
+        let lengths = [(97, 1), (98, 2), (256, 2)];
+        let mut writer = BitWriter::new(vec![]);
+        write_single_distance_block(&mut writer, false)?;
+        write_single_distance_block(&mut writer, false)?;
+        write_literal_only_block(&mut writer, false, &lengths, &[a, b, b, a, eob])?;
+        write_single_distance_block(&mut writer, false)?;
+        write_single_distance_block(&mut writer, true)?;
+        let compressed = writer.into_inner()?;
+        let expected = [b"a".repeat(20), b"abba".to_vec(), b"a".repeat(20)].concat();
+
+        /* With room for a single tree, the one of the third block evicts the other. */
+        for (capacity, hits) in [(1, 2), (2, 3)] {
+            let mut reader =
+                DeflateReader::new(BitReader::new(&compressed[..])).with_tree_cache(capacity);
+            let (_, (_, output)) = reader.deflate(vec![])?;
+            assert_eq!(output, expected);
+            assert_eq!(reader.tree_cache.as_ref().unwrap().hits, hits);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn into_inner() -> Result<()> {
+        use std::io::Read;
+
+        /* A fixed block holding "a", its padding bits set, then a trailer. */
+        let mut writer = BitWriter::new(vec![]);
+        writer.write_bits(0b011, 3)?;
+        writer.write_code(fixed_litlen_code(u16::from(b'a')))?;
+        writer.write_code(fixed_litlen_code(256))?;
+        writer.write_bits(0b111111, 6)?;
+        let mut compressed = writer.into_inner()?;
+        compressed.extend(b"trailer");
+
+        for capacity in [1, 2, 64] {
+            let input = std::io::BufReader::with_capacity(capacity, compressed.as_slice());
+            let mut reader = DeflateReader::new(BitReader::new(input));
+            let (_, (_, output)) = reader.deflate(vec![])?;
+            assert_eq!(output, b"a");
+
+            let mut rest = vec![];
+            reader.into_inner()?.read_to_end(&mut rest)?;
+            assert_eq!(rest, b"trailer");
         }
+        Ok(())
+    }
+
+    #[test]
+    fn deflate64() -> Result<()> {
+        let data: Vec<u8> = (0..40000).map(|i| (i % 251) as u8).collect();
+
+        /* A stored block, then a fixed block with a far and a long match. */
+        let mut writer = BitWriter::new(vec![]);
+        writer.write_bits(0b000, 3)?;
+        let stream = writer.borrow_writer_from_boundary()?;
+        stream.write_all(&(data.len() as u16).to_le_bytes())?;
+        stream.write_all(&(!(data.len() as u16)).to_le_bytes())?;
+        stream.write_all(&data)?;
+        writer.write_bits(0b011, 3)?;
+        writer.write_code(fixed_litlen_code(265))?;
+        writer.write_bits(0, 1)?;
+        writer.write_code(fixed_distance_code(30))?;
+        writer.write_bits(40000 - 32769, 14)?;
+        writer.write_code(fixed_litlen_code(285))?;
+        writer.write_bits(1000 - 3, 16)?;
+        writer.write_code(fixed_distance_code(0))?;
+        writer.write_code(fixed_litlen_code(256))?;
+        let compressed = writer.into_inner()?;
 
-        writer.flush()?;
+        let mut reader = DeflateReader::new_deflate64(BitReader::new(compressed.as_slice()));
+        let (_, (_, output)) = reader.deflate(vec![])?;
+        assert_eq!(output.len(), data.len() + 11 + 1000);
+        assert_eq!(output[..data.len()], data);
+        assert_eq!(output[data.len()..][..11], data[..11]);
+        assert!(output[data.len() + 11..]
+            .iter()
+            .all(|byte| *byte == data[10]));
 
-        Ok((writer.byte_count().try_into()?, writer.crc32()))
+        let mut reader = DeflateReader::new(BitReader::new(compressed.as_slice()));
+        let err = reader.deflate(vec![]).unwrap_err();
+        assert!(matches!(
+            err,
+            DecodeError::DistanceTooLarge { distance: 40000 }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "distance 40000 exceeds the maximum of 32768"
+        );
+        Ok(())
     }
 }
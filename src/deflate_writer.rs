@@ -0,0 +1,149 @@
+#![forbid(unsafe_code)]
+
+use alloc::vec::Vec;
+
+use crate::io::{self, Write};
+
+use crate::bit_writer::BitWriter;
+use crate::deflate::CompressionType;
+use crate::huffman_coding::{
+    encode_distance, encode_length, fixed_distance_code, fixed_litlen_code,
+};
+use crate::lz77::{Matcher, Token};
+
+////////////////////////////////////////////////////////////////////////////////
+
+const MAX_STORED_LEN: usize = 65535;
+const END_OF_BLOCK: u16 = 256;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompressionLevel {
+    /// Stored blocks only, the data is copied as is.
+    Store,
+    /// Fixed Huffman codes, with LZ77 matches found greedily.
+    #[default]
+    Fixed,
+}
+
+pub struct DeflateWriter<T> {
+    bit_writer: BitWriter<T>,
+    level: CompressionLevel,
+    matcher: Matcher,
+    tokens: Vec<Token>,
+}
+
+impl<T: Write> DeflateWriter<T> {
+    pub fn new(writer: T, level: CompressionLevel) -> Self {
+        Self {
+            bit_writer: BitWriter::new(writer),
+            level,
+            matcher: Matcher::new(),
+            tokens: Vec::new(),
+        }
+    }
+
+    /// Compress `data` into one or more blocks. The last block of the stream has to be
+    /// written with `is_final` set, after which nothing else may be written.
+    pub fn write_block(&mut self, data: &[u8], is_final: bool) -> io::Result<()> {
+        match self.level {
+            CompressionLevel::Store => self.write_stored(data, is_final),
+            CompressionLevel::Fixed => self.write_fixed(data, is_final),
+        }
+    }
+
+    /// Return the underlying writer, positioned right after the last block.
+    pub fn into_inner(self) -> io::Result<T> {
+        self.bit_writer.into_inner()
+    }
+
+    fn write_block_header(&mut self, is_final: bool, kind: CompressionType) -> io::Result<()> {
+        self.bit_writer.write_bits(is_final.into(), 1)?;
+        self.bit_writer.write_bits(kind as u32, 2)
+    }
+
+    fn write_stored(&mut self, data: &[u8], is_final: bool) -> io::Result<()> {
+        if data.is_empty() {
+            /* Empty data still takes a block, as it may be the last one. */
+            return self.write_stored_chunk(&[], is_final);
+        }
+        let mut chunks = data.chunks(MAX_STORED_LEN).peekable();
+        while let Some(chunk) = chunks.next() {
+            let is_last = chunks.peek().is_none();
+            self.write_stored_chunk(chunk, is_final && is_last)?;
+        }
+        Ok(())
+    }
+
+    fn write_stored_chunk(&mut self, chunk: &[u8], is_final: bool) -> io::Result<()> {
+        self.write_block_header(is_final, CompressionType::Uncompressed)?;
+        let len = chunk.len() as u16;
+        let writer = self.bit_writer.borrow_writer_from_boundary()?;
+        writer.write_all(&len.to_le_bytes())?;
+        writer.write_all(&(!len).to_le_bytes())?;
+        writer.write_all(chunk)
+    }
+
+    fn write_fixed(&mut self, data: &[u8], is_final: bool) -> io::Result<()> {
+        self.write_block_header(is_final, CompressionType::FixedTree)?;
+        self.tokens.clear();
+        self.matcher.tokenize(data, &mut self.tokens);
+        for token in &self.tokens {
+            match *token {
+                Token::Literal(byte) => {
+                    self.bit_writer
+                        .write_code(fixed_litlen_code(u16::from(byte)))?;
+                }
+                Token::Match { len, dist } => {
+                    let (symbol, extra) = encode_length(len);
+                    self.bit_writer.write_code(fixed_litlen_code(symbol))?;
+                    self.bit_writer
+                        .write_bits(extra.bits().into(), extra.len())?;
+                    let (symbol, extra) = encode_distance(dist);
+                    self.bit_writer.write_code(fixed_distance_code(symbol))?;
+                    self.bit_writer
+                        .write_bits(extra.bits().into(), extra.len())?;
+                }
+            }
+        }
+        self.bit_writer.write_code(fixed_litlen_code(END_OF_BLOCK))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_reader::BitReader;
+    use crate::deflate::DeflateReader;
+
+    fn round_trip(level: CompressionLevel, blocks: &[&[u8]]) -> Vec<u8> {
+        let mut writer = DeflateWriter::new(vec![], level);
+        for (i, block) in blocks.iter().enumerate() {
+            writer.write_block(block, i + 1 == blocks.len()).unwrap();
+        }
+        let compressed = writer.into_inner().unwrap();
+
+        let mut input = compressed.as_slice();
+        let mut reader = DeflateReader::new(BitReader::new(&mut input));
+        let (_, (_, output)) = reader.deflate(vec![]).unwrap();
+        assert!(input.is_empty());
+        output
+    }
+
+    #[test]
+    fn write_block() {
+        let long: Vec<u8> = (0..200_000).map(|i| (i * 7 % 251) as u8).collect();
+        for level in [CompressionLevel::Store, CompressionLevel::Fixed] {
+            assert_eq!(round_trip(level, &[b""]), b"");
+            assert_eq!(
+                round_trip(level, &[b"hello, ", b"", b"world"]),
+                b"hello, world"
+            );
+            assert_eq!(
+                round_trip(level, &[&long, b"!"]),
+                [&long[..], b"!"].concat()
+            );
+        }
+    }
+}
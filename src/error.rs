@@ -0,0 +1,289 @@
+#![forbid(unsafe_code)]
+
+use core::{error, fmt};
+
+use crate::io;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Part of the stream in which the input ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EofContext {
+    Header,
+    Body,
+    Footer,
+}
+
+impl fmt::Display for EofContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Header => write!(f, "header"),
+            Self::Body => write!(f, "body"),
+            Self::Footer => write!(f, "footer"),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Text field of a gzip header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeaderField {
+    Name,
+    Comment,
+}
+
+impl fmt::Display for HeaderField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Name => write!(f, "name"),
+            Self::Comment => write!(f, "comment"),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub type Result<T, E = DecodeError> = core::result::Result<T, E>;
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Io(io::Error),
+    UnexpectedEof {
+        context: EofContext,
+    },
+    BadMagic,
+    UnsupportedCompressionMethod(u8),
+    InvalidHeader(&'static str),
+    HeaderCrcMismatch,
+    /// NAME or COMMENT isn't valid UTF-8, with `HeaderEncoding::Utf8`.
+    InvalidHeaderString {
+        field: HeaderField,
+    },
+    /// BTYPE 3 in the header of the `block_index`th block (from zero), which starts
+    /// `bit_offset` bits into the deflate stream.
+    ReservedBlockType {
+        block_index: u64,
+        bit_offset: u64,
+    },
+    /// NLEN of a stored block isn't the one's complement of LEN.
+    StoredBlockLengthMismatch {
+        len: u16,
+        nlen: u16,
+    },
+    InvalidHuffmanTable(&'static str),
+    /// HLIT, HDIST or HCLEN of a dynamic block header counts more codes than exist.
+    InvalidDynamicHeader {
+        field: &'static str,
+        value: u16,
+        max: u16,
+    },
+    /// None of the codes matches the `len` bits read, given MSB-first in `bits`.
+    InvalidHuffmanCode {
+        bits: u16,
+        len: u8,
+    },
+    InvalidSymbol(u16),
+    /// A length code in a block whose distance code lengths are all zero.
+    NoDistanceCodes,
+    /// A back-reference with distance 0.
+    InvalidDistance,
+    /// A back-reference further than the 32 KiB window of DEFLATE.
+    DistanceTooLarge {
+        distance: usize,
+    },
+    /// A back-reference to before the first byte of the output and the dictionary.
+    DistanceBeforeStart {
+        distance: usize,
+        available: usize,
+    },
+    /// A back-reference reaches further than the configured window.
+    DistanceTooFar {
+        distance: usize,
+        window_size: usize,
+    },
+    /// Strict mode only: bits padding the final block to a byte boundary aren't zero.
+    NonZeroPadding(u8),
+    /// The CRC-32 in the footer, and the one of the decompressed data.
+    CrcMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    /// ISIZE in the footer, and the full size of the decompressed data, which is
+    /// only compared modulo 2^32.
+    LengthMismatch {
+        expected: u32,
+        actual: u64,
+    },
+    Adler32Mismatch,
+    MissingDictionary(u32),
+    TooManyMembers,
+    /// The output would exceed the limit set with `Decompressor::max_output`, after
+    /// `written` bytes of it.
+    OutputLimitExceeded {
+        limit: u64,
+        written: u64,
+    },
+}
+
+impl DecodeError {
+    /// Attribute an unexpected end of input to `context`. Other errors are kept as is.
+    pub(crate) fn in_context(self, context: EofContext) -> Self {
+        match self {
+            Self::UnexpectedEof { .. } => Self::UnexpectedEof { context },
+            err => err,
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "i/o error: {}", err),
+            Self::UnexpectedEof { context } => {
+                write!(f, "unexpected end of input in {}", context)
+            }
+            Self::BadMagic => write!(f, "wrong id values"),
+            Self::UnsupportedCompressionMethod(method @ 0..=7) => {
+                write!(f, "unsupported compression method {} (reserved)", method)
+            }
+            Self::UnsupportedCompressionMethod(method) => {
+                write!(f, "unsupported compression method {} (undefined)", method)
+            }
+            Self::InvalidHeader(what) => write!(f, "invalid header: {}", what),
+            Self::HeaderCrcMismatch => write!(f, "header crc16 check failed"),
+            Self::InvalidHeaderString { field } => {
+                write!(f, "invalid header: {} is not valid utf-8", field)
+            }
+            Self::ReservedBlockType {
+                block_index,
+                bit_offset,
+            } => write!(
+                f,
+                "unsupported block type in block {} at bit {}",
+                block_index, bit_offset
+            ),
+            Self::StoredBlockLengthMismatch { len, nlen } => write!(
+                f,
+                "nlen check failed: len is {:#06x}, nlen is {:#06x}",
+                len, nlen
+            ),
+            Self::InvalidHuffmanTable(what) => write!(f, "invalid huffman table: {}", what),
+            Self::InvalidDynamicHeader { field, value, max } => write!(
+                f,
+                "invalid dynamic block header: {} is {}, at most {} allowed",
+                field, value, max
+            ),
+            Self::InvalidHuffmanCode { len: 0, .. } => {
+                write!(f, "invalid huffman code: the table has no codes")
+            }
+            Self::InvalidHuffmanCode { bits, len } => write!(
+                f,
+                "invalid huffman code: no code matches {:0width$b} ({} bits)",
+                bits,
+                len,
+                width = usize::from(*len)
+            ),
+            Self::InvalidSymbol(symbol) => write!(f, "invalid symbol {}", symbol),
+            Self::NoDistanceCodes => {
+                write!(f, "back-reference in a block without distance codes")
+            }
+            Self::InvalidDistance => write!(f, "invalid distance 0"),
+            Self::DistanceTooLarge { distance } => {
+                write!(f, "distance {} exceeds the maximum of 32768", distance)
+            }
+            Self::DistanceBeforeStart {
+                distance,
+                available,
+            } => write!(
+                f,
+                "distance {} reaches before the start of the data, {} bytes are available",
+                distance, available
+            ),
+            Self::DistanceTooFar {
+                distance,
+                window_size,
+            } => write!(
+                f,
+                "distance {} exceeds the window of {} bytes",
+                distance, window_size
+            ),
+            Self::NonZeroPadding(bits) => {
+                write!(f, "padding after the final block is {:#b}, not zero", bits)
+            }
+            Self::CrcMismatch { expected, actual } => write!(
+                f,
+                "crc32 check failed: the footer has {:#010x}, the data has {:#010x}",
+                expected, actual
+            ),
+            Self::LengthMismatch { expected, actual } => write!(
+                f,
+                "length check failed: ISIZE is {}, the data has {} bytes",
+                expected, actual
+            ),
+            Self::Adler32Mismatch => write!(f, "adler32 check failed"),
+            Self::MissingDictionary(id) => {
+                write!(f, "preset dictionary {:#010x} is required", id)
+            }
+            Self::TooManyMembers => write!(f, "too many members"),
+            Self::OutputLimitExceeded { limit, written } => write!(
+                f,
+                "output limit of {} bytes exceeded after {} bytes",
+                limit, written
+            ),
+        }
+    }
+}
+
+impl error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DecodeError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => Self::UnexpectedEof {
+                context: EofContext::Body,
+            },
+            _ => Self::Io(err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<DecodeError> for io::Error {
+    fn from(err: DecodeError) -> Self {
+        match err {
+            DecodeError::Io(err) => err,
+            DecodeError::UnexpectedEof { .. } => io::Error::new(io::ErrorKind::UnexpectedEof, err),
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
+
+/// Return `$err` unless `$cond` holds.
+macro_rules! ensure {
+    ($cond:expr, $err:expr) => {
+        if !$cond {
+            return Err($err.into());
+        }
+    };
+}
+
+pub(crate) use ensure;
+
+/// Same as `log::trace!`, but compiled in only with the `trace-symbols` feature. Meant
+/// for messages emitted once per decoded symbol.
+macro_rules! trace_symbol {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "trace-symbols")]
+        log::trace!($($arg)*);
+    };
+}
+
+pub(crate) use trace_symbol;
@@ -1,24 +1,30 @@
 #![forbid(unsafe_code)]
 
-use std::io::{BufRead, Write};
+use std::io::{BufRead, Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::{ensure, Result};
-use byteorder::{LittleEndian, ReadBytesExt};
-use crc::Crc;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use crc::{Crc, Digest};
 use log::*;
 
 use crate::{
     bit_reader::BitReader,
+    decoder::MemberReader,
     deflate::DeflateReader,
-    //tracking_writer::TrackingWriter,
+    deflate_writer::{CompressionLevel, DeflateWriter},
+    error::{ensure, DecodeError, EofContext, HeaderField, Result},
+    tracking_writer::TrackingWriter,
 };
 
 ////////////////////////////////////////////////////////////////////////////////
 
-const ID1: u8 = 0x1f;
-const ID2: u8 = 0x8b;
+pub(crate) const ID1: u8 = 0x1f;
+pub(crate) const ID2: u8 = 0x8b;
 
-const CM_DEFLATE: u8 = 8;
+pub(crate) const CM_DEFLATE: u8 = 8;
+
+/// The first bytes of a member compressed with deflate.
+pub(crate) const MAGIC: [u8; 3] = [ID1, ID2, CM_DEFLATE];
 
 const FTEXT_OFFSET: u8 = 0;
 const FHCRC_OFFSET: u8 = 1;
@@ -26,13 +32,25 @@ const FEXTRA_OFFSET: u8 = 2;
 const FNAME_OFFSET: u8 = 3;
 const FCOMMENT_OFFSET: u8 = 4;
 
+/// Computes the CRC-32 which FHCRC is taken from, see `header_crc16_from_crc32`.
+static HEADER_CRC: Crc<u32> = Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+/// FHCRC as defined by RFC 1952: the two least significant bytes of the CRC-32 of
+/// the header bytes before it. This is not any of the actual CRC-16 algorithms, and
+/// computing one of those instead would break compatibility with gzip.
+fn header_crc16_from_crc32(crc32: u32) -> u16 {
+    (crc32 & 0xffff) as u16
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct MemberHeader {
     pub compression_method: CompressionMethod,
     pub modification_time: u32,
     pub extra: Option<Vec<u8>>,
+    /// NAME and COMMENT are ISO-8859-1 in the stream, see `name_bytes` and
+    /// `comment_bytes` for the raw fields.
     pub name: Option<String>,
     pub comment: Option<String>,
     pub extra_flags: u8,
@@ -42,30 +60,97 @@ pub struct MemberHeader {
 }
 
 impl MemberHeader {
-    pub fn crc16(&self) -> u16 {
-        let crc = Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
-        let mut digest = crc.digest();
+    /// The NAME field as stored in the stream, that is `name` encoded as ISO-8859-1.
+    /// Fails if the name has characters outside of ISO-8859-1.
+    pub fn name_bytes(&self) -> Result<Option<Vec<u8>>> {
+        encode_latin1_field(&self.name, "name is not representable in iso-8859-1")
+    }
 
-        digest.update(&[ID1, ID2, self.compression_method.into(), self.flags().0]);
-        digest.update(&self.modification_time.to_le_bytes());
-        digest.update(&[self.extra_flags, self.os]);
+    /// Same as `name_bytes`, but for the COMMENT field.
+    pub fn comment_bytes(&self) -> Result<Option<Vec<u8>>> {
+        encode_latin1_field(&self.comment, "comment is not representable in iso-8859-1")
+    }
 
+    /// Write the header the way it is laid out in a gzip stream, including the
+    /// header CRC if `has_crc` is set.
+    pub fn write<W: Write>(&self, output: &mut W) -> Result<()> {
+        let mut header = vec![];
+        let writer = &mut header;
+        writer.write_all(&[ID1, ID2, self.compression_method.into(), self.flags().0])?;
+        writer.write_u32::<LittleEndian>(self.modification_time)?;
+        writer.write_all(&[self.extra_flags, self.os])?;
         if let Some(extra) = &self.extra {
-            digest.update(&(extra.len() as u16).to_le_bytes());
-            digest.update(extra);
+            let len = u16::try_from(extra.len())
+                .map_err(|_| DecodeError::InvalidHeader("extra field is too long"))?;
+            writer.write_u16::<LittleEndian>(len)?;
+            writer.write_all(extra)?;
+        }
+        if let Some(name) = self.name_bytes()? {
+            ensure!(
+                !name.contains(&0),
+                DecodeError::InvalidHeader("name contains a zero byte")
+            );
+            writer.write_all(&name)?;
+            writer.write_u8(0)?;
+        }
+        if let Some(comment) = self.comment_bytes()? {
+            ensure!(
+                !comment.contains(&0),
+                DecodeError::InvalidHeader("comment contains a zero byte")
+            );
+            writer.write_all(&comment)?;
+            writer.write_u8(0)?;
+        }
+        if self.has_crc {
+            let crc = header_crc16_from_crc32(HEADER_CRC.checksum(&header));
+            header.write_u16::<LittleEndian>(crc)?;
         }
+        output.write_all(&header)?;
+        Ok(())
+    }
 
-        if let Some(name) = &self.name {
-            digest.update(name.as_bytes());
-            digest.update(&[0]);
+    /// Split the EXTRA field into its SI1/SI2-tagged subfields.
+    pub fn extra_subfields(&self) -> Result<Vec<ExtraSubField>> {
+        let mut subfields = vec![];
+        let mut extra: &[u8] = match &self.extra {
+            Some(extra) => extra,
+            None => return Ok(subfields),
+        };
+        while !extra.is_empty() {
+            ensure!(
+                extra.len() >= 4,
+                DecodeError::InvalidHeader("truncated extra subfield header")
+            );
+            let id = [extra[0], extra[1]];
+            let len: usize = u16::from_le_bytes([extra[2], extra[3]]).into();
+            ensure!(
+                extra.len() - 4 >= len,
+                DecodeError::InvalidHeader("extra subfield exceeds extra field")
+            );
+            subfields.push(ExtraSubField {
+                id,
+                data: extra[4..4 + len].to_vec(),
+            });
+            extra = &extra[4 + len..];
         }
+        Ok(subfields)
+    }
 
-        if let Some(comment) = &self.comment {
-            digest.update(comment.as_bytes());
-            digest.update(&[0]);
+    /// Modification time of the original file, `None` if the member has no timestamp.
+    pub fn modified_at(&self) -> Option<SystemTime> {
+        match self.modification_time {
+            0 => None,
+            mtime => Some(UNIX_EPOCH + Duration::from_secs(mtime.into())),
         }
+    }
 
-        (digest.finalize() & 0xffff) as u16
+    pub fn os_kind(&self) -> OperatingSystem {
+        self.os.into()
+    }
+
+    /// XFL as defined for the deflate compression method.
+    pub fn level_hint(&self) -> DeflateLevelHint {
+        self.extra_flags.into()
     }
 
     pub fn flags(&self) -> MemberFlags {
@@ -79,6 +164,30 @@ impl MemberHeader {
     }
 }
 
+/// Decode ISO-8859-1, whose characters are the first 256 Unicode scalar values.
+fn decode_latin1(bytes: &[u8]) -> String {
+    bytes.iter().copied().map(char::from).collect()
+}
+
+fn encode_latin1_field(field: &Option<String>, err: &'static str) -> Result<Option<Vec<u8>>> {
+    field
+        .as_deref()
+        .map(|text| {
+            text.chars()
+                .map(|c| u8::try_from(c).map_err(|_| DecodeError::InvalidHeader(err)))
+                .collect()
+        })
+        .transpose()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtraSubField {
+    pub id: [u8; 2],
+    pub data: Vec<u8>,
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -87,6 +196,18 @@ pub enum CompressionMethod {
     Unknown(u8),
 }
 
+impl CompressionMethod {
+    /// Whether members compressed with this method can be decompressed.
+    pub fn is_supported(&self) -> bool {
+        matches!(self, Self::Deflate)
+    }
+
+    /// Methods 0 to 7, which RFC 1952 reserves. Higher methods but 8 are undefined.
+    pub fn is_reserved(&self) -> bool {
+        matches!(self, Self::Unknown(0..=7))
+    }
+}
+
 impl From<u8> for CompressionMethod {
     fn from(value: u8) -> Self {
         match value {
@@ -105,9 +226,132 @@ impl From<CompressionMethod> for u8 {
     }
 }
 
+/// Methods 0 to 7 are reserved by RFC 1952, so the default is never mistaken for a
+/// supported one.
 impl Default for CompressionMethod {
     fn default() -> Self {
-        Self::Unknown(42)
+        Self::Unknown(0)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OperatingSystem {
+    Fat,
+    Amiga,
+    Vms,
+    Unix,
+    VmCms,
+    AtariTos,
+    Hpfs,
+    Macintosh,
+    ZSystem,
+    CpM,
+    Tops20,
+    Ntfs,
+    Qdos,
+    AcornRiscos,
+    Unknown,
+}
+
+impl From<u8> for OperatingSystem {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Self::Fat,
+            1 => Self::Amiga,
+            2 => Self::Vms,
+            3 => Self::Unix,
+            4 => Self::VmCms,
+            5 => Self::AtariTos,
+            6 => Self::Hpfs,
+            7 => Self::Macintosh,
+            8 => Self::ZSystem,
+            9 => Self::CpM,
+            10 => Self::Tops20,
+            11 => Self::Ntfs,
+            12 => Self::Qdos,
+            13 => Self::AcornRiscos,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+impl From<OperatingSystem> for u8 {
+    fn from(os: OperatingSystem) -> u8 {
+        match os {
+            OperatingSystem::Fat => 0,
+            OperatingSystem::Amiga => 1,
+            OperatingSystem::Vms => 2,
+            OperatingSystem::Unix => 3,
+            OperatingSystem::VmCms => 4,
+            OperatingSystem::AtariTos => 5,
+            OperatingSystem::Hpfs => 6,
+            OperatingSystem::Macintosh => 7,
+            OperatingSystem::ZSystem => 8,
+            OperatingSystem::CpM => 9,
+            OperatingSystem::Tops20 => 10,
+            OperatingSystem::Ntfs => 11,
+            OperatingSystem::Qdos => 12,
+            OperatingSystem::AcornRiscos => 13,
+            OperatingSystem::Unknown => 255,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// What the compressor says about its settings in XFL.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeflateLevelHint {
+    /// Maximum compression, slowest algorithm.
+    MaxCompression,
+    /// Fastest algorithm.
+    Fastest,
+    Unspecified(u8),
+}
+
+impl From<u8> for DeflateLevelHint {
+    fn from(value: u8) -> Self {
+        match value {
+            2 => Self::MaxCompression,
+            4 => Self::Fastest,
+            value => Self::Unspecified(value),
+        }
+    }
+}
+
+impl From<DeflateLevelHint> for u8 {
+    fn from(hint: DeflateLevelHint) -> u8 {
+        match hint {
+            DeflateLevelHint::MaxCompression => 2,
+            DeflateLevelHint::Fastest => 4,
+            DeflateLevelHint::Unspecified(value) => value,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// How NAME and COMMENT are decoded into `MemberHeader::name` and `comment`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HeaderEncoding {
+    /// ISO-8859-1 as required by RFC 1952, which accepts any bytes.
+    #[default]
+    Latin1,
+    /// UTF-8 as written by some producers in practice. Invalid UTF-8 is an error,
+    /// and `name_bytes` and `comment_bytes` no longer give the raw fields.
+    Utf8,
+}
+
+impl HeaderEncoding {
+    fn decode(self, bytes: Vec<u8>, field: HeaderField) -> Result<String> {
+        match self {
+            Self::Latin1 => Ok(decode_latin1(&bytes)),
+            Self::Utf8 => {
+                String::from_utf8(bytes).map_err(|_| DecodeError::InvalidHeaderString { field })
+            }
+        }
     }
 }
 
@@ -173,52 +417,248 @@ impl MemberFlags {
 
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct MemberFooter {
     pub data_crc32: u32,
     pub data_size: u32,
 }
 
+impl MemberFooter {
+    pub(crate) fn read<T: BufRead>(reader: &mut T) -> Result<Self> {
+        let mut read_u32 = || {
+            reader
+                .read_u32::<LittleEndian>()
+                .map_err(|err| DecodeError::from(err).in_context(EofContext::Footer))
+        };
+        Ok(Self {
+            data_crc32: read_u32()?,
+            data_size: read_u32()?,
+        })
+    }
+
+    pub(crate) fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_u32::<LittleEndian>(self.data_crc32)?;
+        writer.write_u32::<LittleEndian>(self.data_size)?;
+        Ok(())
+    }
+
+    /// Check the footer against the CRC-32, if given, and the size of the decompressed
+    /// data. ISIZE only holds the size modulo 2^32, so larger outputs are truncated.
+    pub(crate) fn verify(&self, data_size: u64, data_crc32: Option<u32>) -> Result<()> {
+        if let Some(data_crc32) = data_crc32 {
+            ensure!(
+                self.data_crc32 == data_crc32,
+                DecodeError::CrcMismatch {
+                    expected: self.data_crc32,
+                    actual: data_crc32,
+                }
+            );
+        }
+        ensure!(
+            (data_size & 0xffff_ffff) as u32 == self.data_size,
+            DecodeError::LengthMismatch {
+                expected: self.data_size,
+                actual: data_size,
+            }
+        );
+        Ok(())
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
+/// Everything known about a decompressed member, e.g. to build an index for
+/// random access into a multi-member stream.
+#[derive(Clone, Debug)]
+pub struct MemberInfo {
+    pub header: MemberHeader,
+    pub footer: MemberFooter,
+    /// Bytes the member occupies in the stream, header and footer included.
+    pub compressed_len: u64,
+    /// Bytes of decompressed data, not truncated to 32 bits unlike ISIZE.
+    pub uncompressed_len: u64,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Size of the CRC32 and ISIZE fields.
+pub(crate) const FOOTER_LEN: u64 = 8;
+
 pub struct GzipReader<T> {
     reader: T,
+    /// The parsed header and the number of bytes it took.
+    header: Option<(MemberHeader, u64)>,
+    encoding: HeaderEncoding,
+    strict_padding: bool,
+    tree_cache: Option<usize>,
 }
 
 impl<T: BufRead> GzipReader<T> {
     pub fn new(reader: T) -> Self {
-        Self { reader }
+        Self {
+            reader,
+            header: None,
+            encoding: HeaderEncoding::default(),
+            strict_padding: false,
+            tree_cache: None,
+        }
+    }
+
+    /// Decode NAME and COMMENT with `encoding` instead of ISO-8859-1.
+    pub fn header_encoding(mut self, encoding: HeaderEncoding) -> Self {
+        self.encoding = encoding;
+        self
     }
 
-    pub fn decompress<W: Write>(mut self, output: W) -> Result<(T, W)> {
+    /// Reject set bits padding the end of the deflate stream, see
+    /// `DeflateReader::strict_padding`.
+    pub fn strict_padding(mut self, strict: bool) -> Self {
+        self.strict_padding = strict;
+        self
+    }
+
+    /// Keep the codings of the last `capacity` distinct dynamic blocks, see
+    /// `DeflateReader::with_tree_cache`.
+    pub fn tree_cache(mut self, capacity: usize) -> Self {
+        self.tree_cache = Some(capacity);
+        self
+    }
+
+    /// Parse the member header, leaving the reader at the start of the deflate stream.
+    /// Repeated calls return the already parsed header.
+    pub fn read_header(&mut self) -> Result<MemberHeader> {
+        if let Some((header, _)) = &self.header {
+            return Ok(header.clone());
+        }
         info!("parsing gzip header");
-        let (_header, _flags) = Self::parse_header(&mut self.reader)?;
+        let (header, _flags, len) = Self::parse_header_counted(&mut self.reader, self.encoding)?;
+        self.header = Some((header.clone(), len));
+        Ok(header)
+    }
+
+    /// Decompress this member lazily: reading returns EOF at the end of the member,
+    /// and `MemberReader::into_inner` then gives the reader back after the footer.
+    pub fn member_reader(self) -> MemberReader<T> {
+        MemberReader::new(self)
+    }
+
+    /// The reader, with a `DeflateReader` over it set up with the options of this one.
+    pub(crate) fn into_deflate_reader(self) -> DeflateReader<T> {
+        new_deflate_reader(
+            BitReader::new(self.reader),
+            self.strict_padding,
+            self.tree_cache,
+        )
+    }
+
+    /// Iterate over the members of `reader`, each one decompressed as a whole.
+    pub fn members(reader: T) -> Members<T> {
+        Members {
+            reader: Some(reader),
+            writer: TrackingWriter::new(Vec::new()),
+        }
+    }
+
+    pub fn decompress<W: Write>(self, output: W) -> Result<(MemberHeader, T, W)> {
+        let (info, reader, output) = self.decompress_with_info(output)?;
+        Ok((info.header, reader, output))
+    }
+
+    /// Decompress exactly one member, returning the reader positioned right after its
+    /// footer and the footer itself.
+    pub fn decompress_one<W: Write>(self, output: W) -> Result<(T, W, MemberFooter)> {
+        let (info, reader, output) = self.decompress_with_info(output)?;
+        Ok((reader, output, info.footer))
+    }
+
+    /// Same as `decompress`, but also returns the verified footer and the compressed
+    /// and decompressed sizes of the member.
+    pub fn decompress_with_info<W: Write>(self, output: W) -> Result<(MemberInfo, T, W)> {
+        let mut writer = TrackingWriter::new(output);
+        let (info, reader) = self.decompress_member_into(&mut writer, true)?;
+        let (_, output) = writer.crc32();
+        Ok((info, reader, output))
+    }
+
+    /// Same as `decompress_member`, but decodes into `writer`, which is reset first
+    /// so that its buffers are reused from member to member. Unless `verify_size` is
+    /// set, a wrong ISIZE is only logged if the CRC-32 is checked and right.
+    pub(crate) fn decompress_member_into<W: Write>(
+        mut self,
+        writer: &mut TrackingWriter<W>,
+        verify_size: bool,
+    ) -> Result<(MemberInfo, T)> {
+        let header = self.read_header()?;
+        let header_len = self.header.as_ref().map_or(0, |(_, len)| *len);
 
         info!("parsing deflate format");
-        let mut deflate_reader = DeflateReader::new(BitReader::new(&mut self.reader));
-        let (actual_size, (actual_crc, writer)) = deflate_reader.deflate(output)?;
-        let data_crc32 = self.reader.read_u32::<LittleEndian>()?;
-        let data_size = self.reader.read_u32::<LittleEndian>()?;
-        ensure!(data_size == actual_size, "length check failed");
-        ensure!(data_crc32 == actual_crc, "crc32 check failed");
-        Ok((self.reader, writer))
+        writer.reset();
+        let mut deflate_reader = new_deflate_reader(
+            BitReader::new(&mut self.reader),
+            self.strict_padding,
+            self.tree_cache,
+        );
+        deflate_reader.deflate_tracked(writer)?;
+        let body_len = deflate_reader.stats().bytes_in;
+        let footer = MemberFooter::read(&mut self.reader)?;
+        let crc = writer.computes_crc32().then(|| writer.current_crc32());
+        match footer.verify(writer.byte_count(), crc) {
+            Err(DecodeError::LengthMismatch { .. }) if !verify_size && crc.is_some() => warn!(
+                "ISIZE is {}, but the member has {} bytes",
+                footer.data_size,
+                writer.byte_count()
+            ),
+            result => result?,
+        }
+        let info = MemberInfo {
+            header,
+            footer,
+            compressed_len: header_len + body_len + FOOTER_LEN,
+            uncompressed_len: writer.byte_count(),
+        };
+        Ok((info, self.reader))
+    }
+
+    pub(crate) fn parse_header(header: &mut T) -> Result<(MemberHeader, MemberFlags)> {
+        let (header, flags, _) = Self::parse_header_counted(header, HeaderEncoding::Latin1)?;
+        Ok((header, flags))
+    }
+
+    /// Same as `parse_header`, but also returns the length of the header in bytes.
+    pub(crate) fn parse_header_counted(
+        header: &mut T,
+        encoding: HeaderEncoding,
+    ) -> Result<(MemberHeader, MemberFlags, u64)> {
+        let mut header = CrcReader::new(header);
+        Self::parse_header_fields(&mut header, encoding)
+            .map(|(pheader, pflags)| (pheader, pflags, header.len))
+            .map_err(|err| err.in_context(EofContext::Header))
     }
 
-    fn parse_header(header: &mut T) -> Result<(MemberHeader, MemberFlags)> {
+    fn parse_header_fields(
+        header: &mut CrcReader<T>,
+        encoding: HeaderEncoding,
+    ) -> Result<(MemberHeader, MemberFlags)> {
         let id_1 = header.read_u8()?;
-        ensure!(id_1 == ID1, "wrong id values");
+        ensure!(id_1 == ID1, DecodeError::BadMagic);
 
         let id_2 = header.read_u8()?;
-        ensure!(id_2 == ID2, "wrong id values");
+        ensure!(id_2 == ID2, DecodeError::BadMagic);
 
         let mut pheader = MemberHeader {
             compression_method: header.read_u8()?.into(),
             ..Default::default()
         };
         debug!("CM:\t{:?}", pheader.compression_method);
+        if pheader.compression_method.is_reserved() {
+            warn!(
+                "compression method {} is reserved, is the input really gzip?",
+                u8::from(pheader.compression_method)
+            );
+        }
         ensure!(
-            pheader.compression_method == CompressionMethod::Deflate,
-            "unsupported compression method"
+            pheader.compression_method.is_supported(),
+            DecodeError::UnsupportedCompressionMethod(pheader.compression_method.into())
         );
 
         let pflags = MemberFlags(header.read_u8()?);
@@ -229,7 +669,7 @@ impl<T: BufRead> GzipReader<T> {
         pheader.os = header.read_u8()?;
         debug!("MTIME:\t{}", pheader.modification_time);
         debug!("XFL:\t{}", pheader.extra_flags);
-        debug!("OS:\t{}", pheader.os);
+        debug!("OS:\t{} ({:?})", pheader.os, pheader.os_kind());
 
         if pflags.has_extra() {
             let len: usize = header.read_u16::<LittleEndian>()?.into();
@@ -243,16 +683,14 @@ impl<T: BufRead> GzipReader<T> {
         }
 
         if pflags.has_name() {
-            let mut name = vec![];
-            header.read_until(0, &mut name)?;
-            pheader.name = Some(String::from_utf8(name)?);
+            let name = read_zero_terminated(header)?;
+            pheader.name = Some(encoding.decode(name, HeaderField::Name)?);
             debug!("NAME:\t{:?}", pheader.name);
         }
 
         if pflags.has_comment() {
-            let mut comment = vec![];
-            header.read_until(0, &mut comment)?;
-            pheader.comment = Some(String::from_utf8(comment)?);
+            let comment = read_zero_terminated(header)?;
+            pheader.comment = Some(encoding.decode(comment, HeaderField::Comment)?);
             debug!("COMMENT:\t{:?}", pheader.comment);
         }
 
@@ -262,14 +700,423 @@ impl<T: BufRead> GzipReader<T> {
         }
 
         if pflags.has_crc() {
+            let expected = header_crc16_from_crc32(header.digest.clone().finalize());
             let crc = header.read_u16::<LittleEndian>()?;
             debug!("CRC:\t{:#b}", crc);
 
-            /* Caveat: must be set before calculating crc16 of header. */
             pheader.has_crc = true;
-            ensure!(crc == pheader.crc16(), "header crc16 check failed");
+            ensure!(crc == expected, DecodeError::HeaderCrcMismatch);
         }
 
         Ok((pheader, pflags))
     }
 }
+
+fn new_deflate_reader<T: BufRead>(
+    bit_reader: BitReader<T>,
+    strict_padding: bool,
+    tree_cache: Option<usize>,
+) -> DeflateReader<T> {
+    let deflate_reader = DeflateReader::new(bit_reader)
+        .with_reused_tables()
+        .strict_padding(strict_padding);
+    match tree_cache {
+        Some(capacity) => deflate_reader.with_tree_cache(capacity),
+        None => deflate_reader,
+    }
+}
+
+/// Iterator over the members of a gzip stream, yielding the header and the data of
+/// each. It ends at the end of the stream, or right after yielding an error.
+pub struct Members<T> {
+    /// `None` once an error is yielded.
+    reader: Option<T>,
+    writer: TrackingWriter<Vec<u8>>,
+}
+
+impl<T: BufRead> Iterator for Members<T> {
+    type Item = Result<(MemberHeader, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut reader = self.reader.take()?;
+        match reader.fill_buf() {
+            Ok([]) => return None,
+            Ok(_) => {}
+            Err(err) => return Some(Err(err.into())),
+        }
+        let result = GzipReader::new(reader).decompress_member_into(&mut self.writer, true);
+        Some(result.map(|(info, reader)| {
+            self.reader = Some(reader);
+            (info.header, std::mem::take(self.writer.get_mut()))
+        }))
+    }
+}
+
+/// Parse the header of the member at the start of `bytes`, e.g. to sniff the content
+/// before decompressing it. Fails with `UnexpectedEof` in the header if `bytes` end
+/// before the header does, in which case a longer prefix may still parse.
+pub fn peek_member_header(mut bytes: &[u8]) -> Result<MemberHeader> {
+    let (header, _) = GzipReader::parse_header(&mut bytes)?;
+    Ok(header)
+}
+
+/// Whether `bytes` start with a valid header of a deflate member, or at least with its
+/// ID1, ID2 and CM and then nothing invalid up to the end of `bytes`.
+pub fn is_gzip(bytes: &[u8]) -> bool {
+    bytes.len() >= 3
+        && matches!(
+            peek_member_header(bytes),
+            Ok(_) | Err(DecodeError::UnexpectedEof { .. })
+        )
+}
+
+/// Consume the bytes before the first `ID1 ID2 CM_DEFLATE` sequence and the sequence
+/// itself, failing with `BadMagic` if there are more than `max_skip` bytes before it.
+/// Returns the number of bytes skipped before the sequence. As it may be split across
+/// buffers of `reader`, the sequence can't be left in the reader: the header is to be
+/// parsed from `MAGIC` chained with `reader`.
+pub(crate) fn skip_to_magic<T: BufRead>(reader: &mut T, max_skip: u64) -> Result<u64> {
+    let mut consumed = 0;
+    /* Length of the prefix of `MAGIC` at the end of the bytes consumed so far. */
+    let mut matched = 0;
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Err(DecodeError::BadMagic);
+        }
+        let mut len = 0;
+        let mut found = false;
+        for &byte in buf {
+            len += 1;
+            matched = if byte == MAGIC[matched] {
+                matched + 1
+            } else {
+                usize::from(byte == ID1)
+            };
+            if matched == MAGIC.len() {
+                found = true;
+                break;
+            }
+            if consumed + len as u64 - matched as u64 > max_skip {
+                break;
+            }
+        }
+        reader.consume(len);
+        consumed += len as u64;
+        let skipped = consumed - matched as u64;
+        ensure!(skipped <= max_skip, DecodeError::BadMagic);
+        if found {
+            return Ok(skipped);
+        }
+    }
+}
+
+/// Reads the header byte by byte, keeping the CRC-32 and the count of everything read
+/// so far.
+struct CrcReader<'a, T> {
+    inner: &'a mut T,
+    digest: Digest<'static, u32>,
+    len: u64,
+}
+
+impl<'a, T: Read> CrcReader<'a, T> {
+    fn new(inner: &'a mut T) -> Self {
+        Self {
+            inner,
+            digest: HEADER_CRC.digest(),
+            len: 0,
+        }
+    }
+}
+
+impl<T: Read> Read for CrcReader<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.digest.update(&buf[..len]);
+        self.len += len as u64;
+        Ok(len)
+    }
+}
+
+/// Read a NAME or COMMENT field, dropping the terminating zero.
+fn read_zero_terminated<R: Read>(reader: &mut R) -> Result<Vec<u8>> {
+    let mut field = vec![];
+    loop {
+        match reader.read_u8()? {
+            0 => return Ok(field),
+            byte => field.push(byte),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Amount of input compressed into a single call to `DeflateWriter::write_block`.
+const INPUT_CHUNK_SIZE: usize = 65535;
+
+pub struct GzipWriter<T> {
+    writer: T,
+    header: MemberHeader,
+}
+
+impl<T: Write> GzipWriter<T> {
+    /// The compression method of `header` is ignored: members are always deflated.
+    pub fn new(writer: T, header: MemberHeader) -> Self {
+        Self {
+            writer,
+            header: MemberHeader {
+                compression_method: CompressionMethod::Deflate,
+                ..header
+            },
+        }
+    }
+
+    /// Compress all of `input` into a single member.
+    pub fn compress<R: BufRead>(
+        mut self,
+        mut input: R,
+        level: CompressionLevel,
+    ) -> Result<(MemberFooter, T)> {
+        info!("writing gzip header");
+        self.header.write(&mut self.writer)?;
+
+        info!("writing deflate format");
+        let crc = Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+        let mut digest = crc.digest();
+        let mut data_size: u64 = 0;
+        let mut deflate_writer = DeflateWriter::new(&mut self.writer, level);
+        let mut chunk = Vec::with_capacity(INPUT_CHUNK_SIZE);
+        loop {
+            chunk.clear();
+            (&mut input)
+                .take(INPUT_CHUNK_SIZE as u64)
+                .read_to_end(&mut chunk)?;
+            let is_final = input.fill_buf()?.is_empty();
+            digest.update(&chunk);
+            data_size += chunk.len() as u64;
+            deflate_writer.write_block(&chunk, is_final)?;
+            if is_final {
+                break;
+            }
+        }
+        deflate_writer.into_inner()?;
+
+        let footer = MemberFooter {
+            data_crc32: digest.finalize(),
+            data_size: (data_size & 0xffff_ffff) as u32,
+        };
+        footer.write(&mut self.writer)?;
+        Ok((footer, self.writer))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_header() -> Result<()> {
+        let header = MemberHeader {
+            compression_method: CompressionMethod::Deflate,
+            modification_time: 1617120213,
+            extra: Some(vec![b'A', b'P', 2, 0, 1, 2]),
+            name: Some("hello.txt".to_owned()),
+            comment: Some("a comment".to_owned()),
+            extra_flags: 2,
+            os: 3,
+            has_crc: true,
+            is_text: true,
+        };
+        let mut data = vec![];
+        header.write(&mut data)?;
+
+        let mut input = data.as_slice();
+        let (parsed, _) = GzipReader::parse_header(&mut input)?;
+        assert!(input.is_empty());
+        assert_eq!(format!("{:?}", parsed), format!("{:?}", header));
+
+        let header = MemberHeader {
+            name: Some("nul\0".to_owned()),
+            ..header
+        };
+        assert!(matches!(
+            header.write(&mut vec![]),
+            Err(DecodeError::InvalidHeader(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn latin1_name() -> Result<()> {
+        let mut data = vec![0x1f, 0x8b, 8, 0b11000, 0, 0, 0, 0, 0, 255];
+        data.extend(b"caf\xe9\0\xa9 \xff\0");
+        let mut input = data.as_slice();
+        let (header, _) = GzipReader::parse_header(&mut input)?;
+        assert_eq!(header.name.as_deref(), Some("caf\u{e9}"));
+        assert_eq!(header.comment.as_deref(), Some("\u{a9} \u{ff}"));
+        assert_eq!(header.name_bytes()?.unwrap(), b"caf\xe9");
+        assert_eq!(header.comment_bytes()?.unwrap(), b"\xa9 \xff");
+
+        let header = MemberHeader {
+            has_crc: true,
+            ..header
+        };
+        let mut written = vec![];
+        header.write(&mut written)?;
+        assert_eq!(written[10..data.len()], data[10..]);
+        let (parsed, _) = GzipReader::parse_header(&mut written.as_slice())?;
+        assert_eq!(parsed.name, header.name);
+
+        let header = MemberHeader {
+            name: Some("\u{65e5}\u{672c}".to_owned()),
+            ..header
+        };
+        assert!(matches!(
+            header.write(&mut vec![]),
+            Err(DecodeError::InvalidHeader(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn utf8_name() -> Result<()> {
+        let mut data = vec![0x1f, 0x8b, 8, 0b11000, 0, 0, 0, 0, 0, 255];
+        data.extend("\u{65e5}\u{672c}\0caf\u{e9}\0".as_bytes());
+        let header = GzipReader::new(data.as_slice())
+            .header_encoding(HeaderEncoding::Utf8)
+            .read_header()?;
+        assert_eq!(header.name.as_deref(), Some("\u{65e5}\u{672c}"));
+        assert_eq!(header.comment.as_deref(), Some("caf\u{e9}"));
+
+        let mut data = vec![0x1f, 0x8b, 8, 0b11000, 0, 0, 0, 0, 0, 255];
+        data.extend(b"ok\0caf\xe9\0");
+        let err = GzipReader::new(data.as_slice())
+            .header_encoding(HeaderEncoding::Utf8)
+            .read_header()
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            DecodeError::InvalidHeaderString {
+                field: HeaderField::Comment
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "invalid header: comment is not valid utf-8"
+        );
+        assert!(GzipReader::new(data.as_slice()).read_header().is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn skip_to_magic() -> Result<()> {
+        let data = b"\xef\xbb\xbf\x1f\x1f\x8b\x07\x1f\x8b\x08rest";
+        let mut input = &data[..];
+        assert_eq!(super::skip_to_magic(&mut input, 7)?, 7);
+        assert_eq!(input, b"rest");
+
+        /* Prefixes of the sequence end some buffers, and the sequence is split. */
+        for capacity in 1..=4 {
+            let mut input = std::io::BufReader::with_capacity(capacity, &data[..]);
+            assert_eq!(super::skip_to_magic(&mut input, 100)?, 7);
+            let mut rest = vec![];
+            input.read_to_end(&mut rest)?;
+            assert_eq!(rest, b"rest");
+        }
+
+        assert!(matches!(
+            super::skip_to_magic(&mut &data[..], 6),
+            Err(DecodeError::BadMagic)
+        ));
+        assert!(matches!(
+            super::skip_to_magic(&mut &b"no magic here"[..], 100),
+            Err(DecodeError::BadMagic)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn header_crc() -> Result<()> {
+        /* FEXTRA + FNAME + FHCRC, with a name that isn't valid UTF-8. */
+        let mut data = vec![0x1f, 0x8b, 8, 0b1110, 0x78, 0x56, 0x34, 0x12, 0, 3];
+        data.extend([4, 0, b'A', b'P', 0, 0]);
+        data.extend(b"\xff\xfe\0");
+        let crc = header_crc16_from_crc32(HEADER_CRC.checksum(&data));
+        data.extend(crc.to_le_bytes());
+
+        let (header, _) = GzipReader::parse_header(&mut data.as_slice())?;
+        assert!(header.has_crc);
+        assert_eq!(header.extra.as_deref(), Some(&b"AP\0\0"[..]));
+        assert_eq!(header.name_bytes()?.unwrap(), b"\xff\xfe");
+
+        let mut written = vec![];
+        header.write(&mut written)?;
+        assert_eq!(written, data);
+
+        let len = data.len();
+        data[len - 1] ^= 1;
+        assert!(matches!(
+            GzipReader::parse_header(&mut data.as_slice()),
+            Err(DecodeError::HeaderCrcMismatch)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn header_crc16() {
+        /* A header with FEXTRA and FHCRC as written by gzip, FHCRC being 0xa319. */
+        let data: &[u8] = include_bytes!("../data/ok/10-header-crc16.gz");
+        let (header, crc) = data[..107].split_at(105);
+        assert_eq!(crc, [0x19, 0xa3]);
+        assert_eq!(header_crc16_from_crc32(HEADER_CRC.checksum(header)), 0xa319);
+        assert_eq!(header_crc16_from_crc32(0x1234_5678), 0x5678);
+    }
+
+    #[test]
+    fn compression_method() {
+        assert!(CompressionMethod::from(8).is_supported());
+        assert!(!CompressionMethod::from(9).is_supported());
+        assert!(!CompressionMethod::default().is_supported());
+        assert_eq!(u8::from(CompressionMethod::default()), 0);
+        assert!(CompressionMethod::from(7).is_reserved());
+        assert!(!CompressionMethod::from(8).is_reserved());
+        assert!(!CompressionMethod::from(9).is_reserved());
+    }
+
+    #[test]
+    fn verify_footer() {
+        let footer = MemberFooter {
+            data_crc32: 0xdeadbeef,
+            data_size: 5,
+        };
+        assert!(footer.verify(5, Some(0xdeadbeef)).is_ok());
+        assert!(matches!(
+            footer.verify(6, Some(0xdeadbeef)),
+            Err(DecodeError::LengthMismatch {
+                expected: 5,
+                actual: 6
+            })
+        ));
+        assert!(matches!(
+            footer.verify(5, Some(0xdeadbeee)),
+            Err(DecodeError::CrcMismatch {
+                expected: 0xdeadbeef,
+                actual: 0xdeadbeee
+            })
+        ));
+        assert!(footer.verify(5, None).is_ok());
+        assert!(footer.verify(6, None).is_err());
+
+        /* Sizes of 4 GiB and more are compared modulo 2^32. */
+        assert!(footer.verify((1 << 32) + 5, Some(0xdeadbeef)).is_ok());
+        assert!(matches!(
+            footer.verify((1 << 32) + 6, Some(0xdeadbeef)),
+            Err(DecodeError::LengthMismatch {
+                expected: 5,
+                actual: 0x1_0000_0006
+            })
+        ));
+    }
+}
@@ -1,17 +1,97 @@
 #![forbid(unsafe_code)]
 
-use std::{collections::HashMap, convert::TryFrom, io::BufRead};
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+use core::convert::TryFrom;
 
-use anyhow::{anyhow, ensure, Result};
 use log::*;
 
 use crate::bit_reader::{BitReader, BitSequence};
+use crate::error::{ensure, trace_symbol, DecodeError, Result};
+use crate::io::BufRead;
 
 ////////////////////////////////////////////////////////////////////////////////
 
+/// Number of literal/length codes which may have a length, 286 and 287 can't.
+const MAX_HLIT: u16 = 286;
+/// Number of distance codes which may have a length, all 32 in DEFLATE64.
+const MAX_HDIST: u16 = 30;
+const MAX_HDIST_DEFLATE64: u16 = 32;
+const MAX_HCLEN: u16 = 19;
+
+/// Read the code length codes of a dynamic block and the codings they describe.
+/// Distance codes 30 and 31 may only have lengths in DEFLATE64.
 pub fn decode_litlen_distance_trees<T: BufRead>(
     bit_reader: &mut BitReader<T>,
+    deflate64: bool,
+) -> Result<(HuffmanCoding<LitLenToken>, HuffmanCoding<DistanceToken>)> {
+    let (code_lengths, hlit) = read_code_lengths(bit_reader, deflate64)?;
+    let (lit_lengths, dist_lengths) = code_lengths.split_at(hlit);
+
+    Ok((
+        HuffmanCoding::<LitLenToken>::from_lengths(lit_lengths)?,
+        HuffmanCoding::<DistanceToken>::from_lengths(dist_lengths)?,
+    ))
+}
+
+/// Same as `decode_litlen_distance_trees`, but rebuilds `litlen` and `dist` in place,
+/// reusing their allocations. They are unusable after an error.
+pub fn decode_litlen_distance_trees_into<T: BufRead>(
+    bit_reader: &mut BitReader<T>,
+    deflate64: bool,
+    litlen: &mut HuffmanCoding<LitLenToken>,
+    dist: &mut HuffmanCoding<DistanceToken>,
+) -> Result<()> {
+    let (code_lengths, hlit) = read_code_lengths(bit_reader, deflate64)?;
+    let (lit_lengths, dist_lengths) = code_lengths.split_at(hlit);
+    litlen.rebuild_from_lengths(lit_lengths)?;
+    dist.rebuild_from_lengths(dist_lengths)
+}
+
+/// Same as `decode_litlen_distance_trees`, but codings with the same code lengths as
+/// one of the blocks in `cache` are copied from there instead of being rebuilt.
+pub fn decode_litlen_distance_trees_cached<T: BufRead>(
+    bit_reader: &mut BitReader<T>,
+    deflate64: bool,
+    cache: &mut TreeCache,
 ) -> Result<(HuffmanCoding<LitLenToken>, HuffmanCoding<DistanceToken>)> {
+    let (code_lengths, hlit) = read_code_lengths(bit_reader, deflate64)?;
+    if let Some(pos) = cache
+        .entries
+        .iter()
+        .position(|entry| entry.hlit == hlit && entry.code_lengths == code_lengths)
+    {
+        debug!("reusing cached trees");
+        cache.hits += 1;
+        let entry = cache.entries.remove(pos);
+        let codings = (entry.litlen.clone(), entry.dist.clone());
+        cache.entries.insert(0, entry);
+        return Ok(codings);
+    }
+
+    let (lit_lengths, dist_lengths) = code_lengths.split_at(hlit);
+    let litlen = HuffmanCoding::<LitLenToken>::from_lengths(lit_lengths)?;
+    let dist = HuffmanCoding::<DistanceToken>::from_lengths(dist_lengths)?;
+    if cache.entries.len() == cache.capacity {
+        cache.entries.pop();
+    }
+    cache.entries.insert(
+        0,
+        CachedTrees {
+            code_lengths,
+            hlit,
+            litlen: litlen.clone(),
+            dist: dist.clone(),
+        },
+    );
+    Ok((litlen, dist))
+}
+
+/// Read the code lengths of both codings of a dynamic block, returning them along
+/// with the number of literal/length ones, which come first.
+fn read_code_lengths<T: BufRead>(
+    bit_reader: &mut BitReader<T>,
+    deflate64: bool,
+) -> Result<(Vec<usize>, usize)> {
     info!("dynamic tree");
 
     let hlit = bit_reader.read_bits(5)?.bits() + 257;
@@ -25,7 +105,21 @@ pub fn decode_litlen_distance_trees<T: BufRead>(
         16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
     ];
 
-    ensure!(hclen <= 19);
+    let max_hdist = if deflate64 {
+        MAX_HDIST_DEFLATE64
+    } else {
+        MAX_HDIST
+    };
+    for (field, value, max) in [
+        ("HLIT", hlit, MAX_HLIT),
+        ("HDIST", hdist, max_hdist),
+        ("HCLEN", hclen, MAX_HCLEN),
+    ] {
+        ensure!(
+            value <= max,
+            DecodeError::InvalidDynamicHeader { field, value, max }
+        );
+    }
     let mut tree_len = vec![0; 19];
     for i in 0..hclen {
         let len = bit_reader.read_bits(3)?;
@@ -33,15 +127,19 @@ pub fn decode_litlen_distance_trees<T: BufRead>(
     }
     let tree_code_huffman = HuffmanCoding::<TreeCodeToken>::from_lengths(&tree_len)?;
 
-    let mut code_lengths = Vec::<usize>::with_capacity((hlit + hdist).into());
-    loop {
+    let total_len = usize::from(hlit + hdist);
+    let mut code_lengths = Vec::<usize>::with_capacity(total_len);
+    while code_lengths.len() < total_len {
         let code = tree_code_huffman.read_symbol(bit_reader)?;
-        debug!("decode: {:?}", code);
+        trace_symbol!("decode: {:?}", code);
         match code {
             TreeCodeToken::Length(some) => code_lengths.push(some.into()),
             TreeCodeToken::CopyPrev => {
                 let num_repetitions = bit_reader.read_bits(2)?.bits() + 3;
-                ensure!(code_lengths.last().is_some(), "nothing to copy");
+                ensure!(
+                    code_lengths.last().is_some(),
+                    DecodeError::InvalidHuffmanTable("nothing to copy")
+                );
                 let prev_len = *code_lengths.last().unwrap();
                 code_lengths.append(&mut vec![prev_len; num_repetitions.into()]);
             }
@@ -50,38 +148,91 @@ pub fn decode_litlen_distance_trees<T: BufRead>(
                 code_lengths.append(&mut vec![0; (base + extra.bits()).into()]);
             }
         }
-        if code_lengths.len() == (hlit + hdist).into() {
-            break;
+        /* A repetition may not run from the lengths into whatever follows them. */
+        ensure!(
+            code_lengths.len() <= total_len,
+            DecodeError::InvalidHuffmanTable("repetition past HLIT + HDIST code lengths")
+        );
+    }
+
+    Ok((code_lengths, hlit.into()))
+}
+
+/// Code of the literal/length symbol `lit` in the fixed Huffman coding.
+pub fn fixed_litlen_code(lit: u16) -> BitSequence {
+    match lit {
+        0..=143 => BitSequence::new(0b00110000 + lit, 8),
+        144..=255 => BitSequence::new(0b110010000 + lit - 144, 9),
+        256..=279 => BitSequence::new(lit - 256, 7),
+        280..=287 => BitSequence::new(0b11000000 + lit - 280, 8),
+        _ => panic!("invalid literal/length symbol {}", lit),
+    }
+}
+
+/// Code of the distance symbol `dist` in the fixed Huffman coding, 30 and 31 being
+/// valid in DEFLATE64 only.
+pub fn fixed_distance_code(dist: u16) -> BitSequence {
+    assert!(dist < 32, "invalid distance symbol {}", dist);
+    BitSequence::new(dist, 5)
+}
+
+/// Literal/length symbol and extra bits encoding a match of `len` (3 to 258) bytes,
+/// the inverse of `LitLenToken::Length` and `LitLenToken::MaxLength`.
+pub fn encode_length(len: u16) -> (u16, BitSequence) {
+    match len {
+        3..=10 => (len + 254, BitSequence::new(0, 0)),
+        11..=257 => {
+            let value = len - 3;
+            let extra_bits = (15 - value.leading_zeros() - 2) as u8;
+            let symbol = 257 + 4 * u16::from(extra_bits) + (value >> extra_bits);
+            (symbol, BitSequence::new(value, extra_bits))
         }
+        258 => (285, BitSequence::new(0, 0)),
+        _ => panic!("invalid match length {}", len),
     }
+}
 
-    let (lit_lengths, dist_lengths) = code_lengths.split_at(hlit.into());
+/// Distance symbol and extra bits encoding a match distance of `dist` (1 to 32768)
+/// bytes, the inverse of `DistanceToken`.
+pub fn encode_distance(dist: u16) -> (u16, BitSequence) {
+    match dist {
+        1..=4 => (dist - 1, BitSequence::new(0, 0)),
+        5..=32768 => {
+            let value = dist - 1;
+            let extra_bits = (15 - value.leading_zeros() - 1) as u8;
+            let symbol = 2 * u16::from(extra_bits) + (value >> extra_bits);
+            (symbol, BitSequence::new(value, extra_bits))
+        }
+        _ => panic!("invalid match distance {}", dist),
+    }
+}
 
-    Ok((
-        HuffmanCoding::<LitLenToken>::from_lengths(lit_lengths)?,
-        HuffmanCoding::<DistanceToken>::from_lengths(dist_lengths)?,
-    ))
+#[cfg(test)]
+std::thread_local! {
+    /// Number of calls to `get_fixed_coding` made by the current test.
+    pub static FIXED_CODING_BUILDS: core::cell::Cell<usize> = const { core::cell::Cell::new(0) };
 }
 
 pub fn get_fixed_coding() -> Result<(HuffmanCoding<LitLenToken>, HuffmanCoding<DistanceToken>)> {
     info!("fixed tree");
-    let mut litlen_map = HashMap::<BitSequence, LitLenToken>::with_capacity(288);
+    #[cfg(test)]
+    FIXED_CODING_BUILDS.with(|builds| builds.set(builds.get() + 1));
+    let mut litlen_map = BTreeMap::<BitSequence, LitLenToken>::new();
     for lit in 0..=287 {
-        let code = match lit {
-            0..=143 => BitSequence::new(0b00110000 + lit, 8),
-            144..=255 => BitSequence::new(0b110010000 + lit - 144, 9),
-            256..=279 => BitSequence::new(lit - 256, 7),
-            280..=287 => BitSequence::new(0b11000000 + lit - 280, 8),
-            _ => unreachable!(),
-        };
-        litlen_map.insert(code, HuffmanCodeWord(lit).try_into()?);
+        let code = fixed_litlen_code(lit);
+        /* Codes 286 and 287 take part in the code construction but never occur in data. */
+        if let Ok(token) = HuffmanCodeWord(lit).try_into() {
+            litlen_map.insert(code, token);
+        }
     }
     let litlen_coding = HuffmanCoding::<LitLenToken>::new(litlen_map);
 
-    let mut dist_map = HashMap::<BitSequence, DistanceToken>::with_capacity(32);
+    let mut dist_map = BTreeMap::<BitSequence, DistanceToken>::new();
     for lit in 0..=31 {
         let code = BitSequence::new(lit, 5);
-        dist_map.insert(code, HuffmanCodeWord(lit).try_into()?);
+        if let Ok(token) = HuffmanCodeWord(lit).try_into() {
+            dist_map.insert(code, token);
+        }
     }
     let dist_coding = HuffmanCoding::<DistanceToken>::new(dist_map);
 
@@ -90,6 +241,36 @@ pub fn get_fixed_coding() -> Result<(HuffmanCoding<LitLenToken>, HuffmanCoding<D
 
 ////////////////////////////////////////////////////////////////////////////////
 
+/// Codings of the last few distinct dynamic blocks, the most recently used first.
+#[derive(Clone)]
+pub struct TreeCache {
+    entries: Vec<CachedTrees>,
+    capacity: usize,
+    /// Number of blocks whose codings were found in the cache.
+    pub hits: u64,
+}
+
+#[derive(Clone)]
+struct CachedTrees {
+    code_lengths: Vec<usize>,
+    hlit: usize,
+    litlen: HuffmanCoding<LitLenToken>,
+    dist: HuffmanCoding<DistanceToken>,
+}
+
+impl TreeCache {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity != 0);
+        Self {
+            entries: Vec::with_capacity(capacity),
+            capacity,
+            hits: 0,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 #[derive(Clone, Copy, Debug)]
 pub enum TreeCodeToken {
     Length(u8),
@@ -98,12 +279,12 @@ pub enum TreeCodeToken {
 }
 
 impl TryFrom<HuffmanCodeWord> for TreeCodeToken {
-    type Error = anyhow::Error;
+    type Error = DecodeError;
 
     fn try_from(value: HuffmanCodeWord) -> Result<Self> {
         debug!("tree code {}", value.0);
         match value.0 {
-            0..=15 => Ok(Self::Length(value.0.try_into()?)),
+            0..=15 => Ok(Self::Length(value.0 as u8)),
             16 => Ok(Self::CopyPrev),
             17 => Ok(Self::RepeatZero {
                 base: 3,
@@ -113,7 +294,7 @@ impl TryFrom<HuffmanCodeWord> for TreeCodeToken {
                 base: 11,
                 extra_bits: 7,
             }),
-            _ => Err(anyhow!("CL bad code: {}", value.0)),
+            _ => Err(DecodeError::InvalidSymbol(value.0)),
         }
     }
 }
@@ -124,33 +305,35 @@ impl TryFrom<HuffmanCodeWord> for TreeCodeToken {
 pub enum LitLenToken {
     Literal(u8),
     EndOfBlock,
-    Length { base: u16, extra_bits: u8 },
+    Length {
+        base: u16,
+        extra_bits: u8,
+    },
+    /// Code 285, a length of 258 in DEFLATE but of 3 plus 16 extra bits in DEFLATE64.
+    MaxLength,
 }
 
 impl TryFrom<HuffmanCodeWord> for LitLenToken {
-    type Error = anyhow::Error;
+    type Error = DecodeError;
 
     fn try_from(value: HuffmanCodeWord) -> Result<Self> {
         debug!("litlen code {}", value.0);
         match value.0 {
-            0..=255 => Ok(Self::Literal(value.0.try_into()?)),
+            0..=255 => Ok(Self::Literal(value.0 as u8)),
             256 => Ok(Self::EndOfBlock),
             257..=264 => Ok(Self::Length {
                 base: value.0 - 254,
                 extra_bits: 0,
             }),
             265..=284 => {
-                let extra_bits: u8 = ((value.0 - 265) / 4 + 1).try_into()?;
+                let extra_bits = ((value.0 - 265) / 4 + 1) as u8;
                 let len_base = (1 << (extra_bits + 2)) + 3;
                 let base = len_base + ((value.0 - 1) % 4) * (1 << extra_bits);
 
                 Ok(Self::Length { base, extra_bits })
             }
-            285 => Ok(Self::Length {
-                base: 258,
-                extra_bits: 0,
-            }),
-            _ => Err(anyhow!("LL bad code {}", value.0)),
+            285 => Ok(Self::MaxLength),
+            _ => Err(DecodeError::InvalidSymbol(value.0)),
         }
     }
 }
@@ -164,7 +347,7 @@ pub struct DistanceToken {
 }
 
 impl TryFrom<HuffmanCodeWord> for DistanceToken {
-    type Error = anyhow::Error;
+    type Error = DecodeError;
 
     fn try_from(value: HuffmanCodeWord) -> Result<Self> {
         debug!("dist code {}", value.0);
@@ -173,14 +356,15 @@ impl TryFrom<HuffmanCodeWord> for DistanceToken {
                 base: value.0 + 1,
                 extra_bits: 0,
             }),
-            4..=29 => {
+            /* Codes 30 and 31 are only used by DEFLATE64. */
+            4..=31 => {
                 let extra_bits: u8 = value.0 as u8 / 2 - 1;
                 Ok(Self {
                     base: (1 << (extra_bits + 1)) + (value.0 % 2) * (1 << extra_bits) + 1,
                     extra_bits,
                 })
             }
-            _ => Err(anyhow!("D bad code: {}", value.0)),
+            _ => Err(DecodeError::InvalidSymbol(value.0)),
         }
     }
 }
@@ -189,39 +373,129 @@ impl TryFrom<HuffmanCodeWord> for DistanceToken {
 
 const MAX_BITS: usize = 15;
 
+/// Codes up to this length are decoded with a single table lookup.
+const TABLE_BITS: u8 = 9;
+
 #[derive(Clone, Copy)]
 pub struct HuffmanCodeWord(pub u16);
 
+#[derive(Clone)]
 pub struct HuffmanCoding<T> {
-    map: HashMap<BitSequence, T>,
+    /// Symbols ordered by code length, then by code, as in a canonical code.
+    symbols: Vec<T>,
+    /// Number of codes of each length.
+    count: [u16; MAX_BITS + 1],
+    /// Smallest code of each length.
+    first_code: [u16; MAX_BITS + 1],
+    /// Index in `symbols` of the first symbol of each length.
+    offset: [u16; MAX_BITS + 1],
+    /// Indexed by the next `TABLE_BITS` bits in stream order, holds the symbol
+    /// and its code length for every code not longer than `TABLE_BITS`.
+    table: Vec<Option<(T, u8)>>,
+    /// Length of the longest code, so that invalid codes are detected without reading
+    /// more bits than any code has.
+    max_len: u8,
 }
 
 impl<T> HuffmanCoding<T>
 where
-    T: Copy + TryFrom<HuffmanCodeWord, Error = anyhow::Error>,
+    T: Copy + TryFrom<HuffmanCodeWord, Error = DecodeError>,
 {
-    pub fn new(map: HashMap<BitSequence, T>) -> Self {
-        Self { map }
+    /// Codes of the same length in `map` must be consecutive, as they are in a canonical code.
+    pub fn new(map: BTreeMap<BitSequence, T>) -> Self {
+        let mut entries: Vec<_> = map.into_iter().collect();
+        entries.sort_by_key(|(code, _)| (code.len(), code.bits()));
+
+        let mut count = [0; MAX_BITS + 1];
+        let mut first_code = [0; MAX_BITS + 1];
+        let mut offset = [0; MAX_BITS + 1];
+        for (idx, (code, _)) in entries.iter().enumerate() {
+            let len = code.len() as usize;
+            if count[len] == 0 {
+                first_code[len] = code.bits();
+                offset[len] = idx as u16;
+            }
+            debug_assert_eq!(code.bits(), first_code[len] + count[len]);
+            count[len] += 1;
+        }
+        let max_len = entries.last().map_or(0, |(code, _)| code.len());
+
+        let mut coding = Self {
+            symbols: Vec::with_capacity(entries.len()),
+            count,
+            first_code,
+            offset,
+            table: vec![None; 1 << TABLE_BITS],
+            max_len,
+        };
+        for (code, symbol) in entries {
+            coding.symbols.push(symbol);
+            coding.fill_table(code, symbol);
+        }
+        coding
     }
 
     #[allow(unused)]
     pub fn decode_symbol(&self, seq: BitSequence) -> Option<T> {
-        self.map.get(&seq).copied()
+        let len = seq.len() as usize;
+        if len > MAX_BITS {
+            return None;
+        }
+        let index = seq.bits().checked_sub(self.first_code[len])?;
+        if index >= self.count[len] {
+            return None;
+        }
+        Some(self.symbols[(self.offset[len] + index) as usize])
+    }
+
+    /// Whether the coding has no codes at all, as the distance coding of a block
+    /// without back-references may have.
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
     }
 
     pub fn read_symbol<U: BufRead>(&self, bit_reader: &mut BitReader<U>) -> Result<T> {
+        let peeked = bit_reader.peek_bits(TABLE_BITS)?;
+        if peeked.len() == TABLE_BITS {
+            if let Some((symbol, len)) = self.table[peeked.bits() as usize] {
+                bit_reader.skip_bits(len);
+                return Ok(symbol);
+            }
+        }
+        self.read_symbol_bitwise(bit_reader)
+    }
+
+    fn read_symbol_bitwise<U: BufRead>(&self, bit_reader: &mut BitReader<U>) -> Result<T> {
         let mut bits = BitSequence::new(0, 0);
-        while bits.len() < 16 {
-            debug!("reading huffman: {:?}", bits);
+        while bits.len() < self.max_len {
+            trace_symbol!("reading huffman: {:?}", bits);
             bits = bits.concat(bit_reader.read_bits(1)?);
             if let Some(symbol) = self.decode_symbol(bits) {
                 return Ok(symbol);
             }
         }
-        Err(anyhow!(":("))
+        Err(DecodeError::InvalidHuffmanCode {
+            bits: bits.bits(),
+            len: bits.len(),
+        })
     }
 
     pub fn from_lengths(code_lengths: &[usize]) -> Result<Self> {
+        let mut coding = Self {
+            symbols: Vec::new(),
+            count: [0; MAX_BITS + 1],
+            first_code: [0; MAX_BITS + 1],
+            offset: [0; MAX_BITS + 1],
+            table: vec![None; 1 << TABLE_BITS],
+            max_len: 0,
+        };
+        coding.rebuild_from_lengths(code_lengths)?;
+        Ok(coding)
+    }
+
+    /// Same as `from_lengths`, but replaces the coding in place, reusing its buffers.
+    /// The coding is unusable after an error.
+    pub fn rebuild_from_lengths(&mut self, code_lengths: &[usize]) -> Result<()> {
         info!("creating huffman coding from lengths {:#?}", code_lengths);
 
         let mut bl_count: [usize; MAX_BITS + 1] = [0; MAX_BITS + 1];
@@ -231,26 +505,69 @@ where
         bl_count[0] = 0;
         debug!("bl_count: {:#?}", bl_count);
 
-        let mut next_code: [u16; MAX_BITS + 1] = [0; MAX_BITS + 1];
+        /* Kraft sum check: count the codes of each length left unused by shorter ones. */
+        let mut left: isize = 1;
+        for count in &bl_count[1..] {
+            left = 2 * left - *count as isize;
+            ensure!(
+                left >= 0,
+                DecodeError::InvalidHuffmanTable("over-subscribed code lengths")
+            );
+        }
+        let used: usize = bl_count.iter().sum();
+        /* An empty table or a single code of one bit are the only allowed incomplete codes. */
+        ensure!(
+            left == 0 || used == 0 || (used == 1 && bl_count[1] == 1),
+            DecodeError::InvalidHuffmanTable("incomplete code lengths")
+        );
+
         let mut code: u16 = 0;
+        let mut offset: u16 = 0;
         for bits in 1..=MAX_BITS {
             code = (code + bl_count[bits - 1] as u16) << 1;
-            next_code[bits] = code;
+            self.first_code[bits] = code;
+            self.count[bits] = bl_count[bits] as u16;
+            self.offset[bits] = offset;
+            offset += self.count[bits];
         }
-        debug!("next_code: {:#?}", next_code);
-
-        let mut map = HashMap::<BitSequence, T>::new();
-        for (idx, len) in code_lengths.iter().enumerate() {
-            if *len == 0 {
-                continue;
+        debug!("first_code: {:#?}", self.first_code);
+
+        /* Within a length, codes are assigned in the order of the symbols. */
+        self.symbols.clear();
+        self.table.fill(None);
+        self.max_len = 0;
+        for (len, _) in bl_count.iter().enumerate().skip(1).filter(|(_, &n)| n != 0) {
+            let len = len as u8;
+            for (idx, _) in code_lengths
+                .iter()
+                .enumerate()
+                .filter(|(_, code_len)| **code_len == usize::from(len))
+            {
+                let symbol = HuffmanCodeWord(idx as u16).try_into()?;
+                let code = BitSequence::new(
+                    self.first_code[usize::from(len)] + self.symbols.len() as u16
+                        - self.offset[usize::from(len)],
+                    len,
+                );
+                trace_symbol!("new code: {} -> {:?}", idx, code);
+                self.symbols.push(symbol);
+                self.fill_table(code, symbol);
             }
-            let code = BitSequence::new(next_code[*len], *len as u8);
-            map.insert(code, HuffmanCodeWord(idx as u16).try_into()?);
-            debug!("new code: {} -> {:?}", idx, code);
-            next_code[*len] += 1;
+            self.max_len = len;
         }
+        Ok(())
+    }
 
-        Ok(Self::new(map))
+    /// Point all the table entries starting with `code` to `symbol`, if it is short enough.
+    fn fill_table(&mut self, code: BitSequence, symbol: T) {
+        if code.len() > TABLE_BITS {
+            return;
+        }
+        /* Codes are stored MSB-first, but the stream yields their first bit first. */
+        let reversed = code.bits().reverse_bits() >> (16 - code.len());
+        for suffix in 0..1 << (TABLE_BITS - code.len()) {
+            self.table[(reversed | (suffix << code.len())) as usize] = Some((symbol, code.len()));
+        }
     }
 }
 
@@ -259,18 +576,44 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::bit_writer::BitWriter;
 
     #[derive(Clone, Copy, Debug, PartialEq)]
     struct Value(u16);
 
     impl TryFrom<HuffmanCodeWord> for Value {
-        type Error = anyhow::Error;
+        type Error = DecodeError;
 
         fn try_from(x: HuffmanCodeWord) -> Result<Self> {
             Ok(Self(x.0))
         }
     }
 
+    #[test]
+    fn encode_length() {
+        for len in 3..=258 {
+            let (symbol, extra) = super::encode_length(len);
+            match LitLenToken::try_from(HuffmanCodeWord(symbol)).unwrap() {
+                LitLenToken::Length { base, extra_bits } => {
+                    assert_eq!(extra.len(), extra_bits);
+                    assert_eq!(base + extra.bits(), len);
+                }
+                LitLenToken::MaxLength => assert_eq!(len, 258),
+                token => panic!("unexpected token {:?}", token),
+            }
+        }
+    }
+
+    #[test]
+    fn encode_distance() {
+        for dist in 1..=32768 {
+            let (symbol, extra) = super::encode_distance(dist);
+            let token = DistanceToken::try_from(HuffmanCodeWord(symbol)).unwrap();
+            assert_eq!(extra.len(), token.extra_bits);
+            assert_eq!(token.base + extra.bits(), dist);
+        }
+    }
+
     #[test]
     fn from_lengths() -> Result<()> {
         let code = HuffmanCoding::<Value>::from_lengths(&[2, 3, 4, 3, 3, 4, 2])?;
@@ -311,6 +654,140 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn fixed_coding() -> Result<()> {
+        let (litlen, dist) = get_fixed_coding()?;
+        let decode = |bits, len| litlen.decode_symbol(BitSequence::new(bits, len));
+
+        assert!(matches!(
+            decode(0b00110000, 8),
+            Some(LitLenToken::Literal(0))
+        ));
+        assert!(matches!(
+            decode(0b110010000, 9),
+            Some(LitLenToken::Literal(144))
+        ));
+        assert!(matches!(
+            decode(0b0000000, 7),
+            Some(LitLenToken::EndOfBlock)
+        ));
+        assert!(matches!(
+            decode(0b11000101, 8),
+            Some(LitLenToken::MaxLength)
+        ));
+        /* Codes 286 and 287 are part of the code but have no symbol. */
+        assert!(decode(0b11000110, 8).is_none());
+        assert!(decode(0b11000111, 8).is_none());
+
+        assert!(matches!(
+            dist.decode_symbol(BitSequence::new(29, 5)),
+            Some(DistanceToken { base: 24577, .. })
+        ));
+        assert!(matches!(
+            dist.decode_symbol(BitSequence::new(31, 5)),
+            Some(DistanceToken { base: 49153, .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn from_lengths_invalid() {
+        assert!(matches!(
+            HuffmanCoding::<Value>::from_lengths(&[1, 1, 1]),
+            Err(DecodeError::InvalidHuffmanTable(_))
+        ));
+        assert!(matches!(
+            HuffmanCoding::<Value>::from_lengths(&[2, 3, 4, 3, 3, 4, 1]),
+            Err(DecodeError::InvalidHuffmanTable(_))
+        ));
+        assert!(matches!(
+            HuffmanCoding::<Value>::from_lengths(&[2, 2, 2]),
+            Err(DecodeError::InvalidHuffmanTable(_))
+        ));
+        assert!(matches!(
+            HuffmanCoding::<Value>::from_lengths(&[0, 2, 0]),
+            Err(DecodeError::InvalidHuffmanTable(_))
+        ));
+
+        assert!(HuffmanCoding::<Value>::from_lengths(&[0, 1, 0]).is_ok());
+        assert!(HuffmanCoding::<Value>::from_lengths(&[0, 0]).is_ok());
+    }
+
+    #[test]
+    fn dynamic_header_bounds() -> Result<()> {
+        let decode = |hlit: u32, hdist: u32, deflate64| -> Result<()> {
+            let mut writer = BitWriter::new(vec![]);
+            writer.write_bits(hlit - 257, 5)?;
+            writer.write_bits(hdist - 1, 5)?;
+            writer.write_bits(0, 4)?;
+            let data = writer.into_inner()?;
+            decode_litlen_distance_trees(&mut BitReader::new(data.as_slice()), deflate64)?;
+            Ok(())
+        };
+
+        for (hlit, hdist, deflate64, field, max) in [
+            (287, 30, false, "HLIT", 286),
+            (288, 1, true, "HLIT", 286),
+            (286, 31, false, "HDIST", 30),
+            (286, 32, false, "HDIST", 30),
+        ] {
+            match decode(hlit, hdist, deflate64) {
+                Err(DecodeError::InvalidDynamicHeader {
+                    field: actual_field,
+                    value,
+                    max: actual_max,
+                }) => {
+                    assert_eq!(actual_field, field);
+                    assert_eq!(u32::from(value), if field == "HLIT" { hlit } else { hdist });
+                    assert_eq!(actual_max, max);
+                }
+                result => panic!("unexpected result {:?}", result),
+            }
+        }
+
+        /* Within bounds, decoding goes on and runs out of input. */
+        for (hlit, hdist, deflate64) in [(286, 30, false), (286, 32, true)] {
+            assert!(matches!(
+                decode(hlit, hdist, deflate64),
+                Err(DecodeError::UnexpectedEof { .. })
+            ));
+        }
+
+        assert_eq!(
+            decode(287, 1, false).unwrap_err().to_string(),
+            "invalid dynamic block header: HLIT is 287, at most 286 allowed"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn repetition_past_code_lengths() -> Result<()> {
+        let mut writer = BitWriter::new(vec![]);
+        /* HLIT 257, HDIST 1, and only codes 18 and 0 of the code length code, one bit each. */
+        writer.write_bits(0, 5)?;
+        writer.write_bits(0, 5)?;
+        writer.write_bits(0, 4)?;
+        for len in [0, 0, 1, 1] {
+            writer.write_bits(len, 3)?;
+        }
+        /* Two runs of 138 zeros are more than the 258 lengths needed. */
+        for _ in 0..2 {
+            writer.write_bits(1, 1)?;
+            writer.write_bits(127, 7)?;
+        }
+        let mut data = writer.into_inner()?;
+        data.extend([0; 64]);
+
+        assert!(matches!(
+            decode_litlen_distance_trees(&mut BitReader::new(data.as_slice()), false),
+            Err(DecodeError::InvalidHuffmanTable(
+                "repetition past HLIT + HDIST code lengths"
+            ))
+        ));
+        Ok(())
+    }
+
     #[test]
     fn read_symbol() -> Result<()> {
         let code = HuffmanCoding::<Value>::from_lengths(&[2, 3, 4, 3, 3, 4, 2])?;
@@ -328,4 +805,95 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn read_symbol_max_len() -> Result<()> {
+        /* Codes 0, 10, 110 and 1110 leave 1111 unused, which only `new` allows. */
+        let code = HuffmanCoding::new(BTreeMap::from([
+            (BitSequence::new(0b0, 1), Value(0)),
+            (BitSequence::new(0b10, 2), Value(1)),
+            (BitSequence::new(0b110, 3), Value(2)),
+            (BitSequence::new(0b1110, 4), Value(3)),
+        ]));
+        let data: &[u8] = &[0b01111111, 0b11111111];
+        let mut reader = BitReader::new(data);
+        assert_eq!(reader.read_bits(3)?, BitSequence::new(0b111, 3));
+        assert!(matches!(
+            code.read_symbol(&mut reader),
+            Err(DecodeError::InvalidHuffmanCode {
+                bits: 0b1111,
+                len: 4
+            })
+        ));
+        /* No more bits than the longest code are read before giving up. */
+        assert_eq!(reader.bit_position(), 7);
+        assert_eq!(code.read_symbol(&mut reader)?, Value(0));
+        Ok(())
+    }
+
+    #[test]
+    fn read_symbol_single_code() -> Result<()> {
+        let code = HuffmanCoding::<Value>::from_lengths(&[0, 1, 0])?;
+        for data in [&[0b00000010, 0][..], &[0b00000010]] {
+            let mut reader = BitReader::new(data);
+            assert_eq!(code.read_symbol(&mut reader)?, Value(1));
+            assert!(matches!(
+                code.read_symbol(&mut reader),
+                Err(DecodeError::InvalidHuffmanCode { bits: 0b1, len: 1 })
+            ));
+        }
+        let err = code
+            .read_symbol(&mut BitReader::new(&[0xff][..]))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid huffman code: no code matches 1 (1 bits)"
+        );
+
+        let code = HuffmanCoding::<Value>::from_lengths(&[0, 0])?;
+        assert!(code.is_empty());
+        let err = code
+            .read_symbol(&mut BitReader::new(&[0xff, 0xff][..]))
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            DecodeError::InvalidHuffmanCode { bits: 0, len: 0 }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "invalid huffman code: the table has no codes"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn read_symbol_table_matches_bitwise() -> Result<()> {
+        let lengths = [2, 3, 0, 2, 3, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 12];
+        let code = HuffmanCoding::<Value>::from_lengths(&lengths)?;
+
+        let mut state = 0x2545f491u32;
+        let data: Vec<u8> = (0..4096)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+
+        let mut table_reader = BitReader::new(data.as_slice());
+        let mut bitwise_reader = BitReader::new(data.as_slice());
+        loop {
+            match (
+                code.read_symbol(&mut table_reader),
+                code.read_symbol_bitwise(&mut bitwise_reader),
+            ) {
+                (Ok(fast), Ok(slow)) => assert_eq!(fast, slow),
+                (Err(_), Err(_)) => break,
+                (fast, slow) => panic!("paths diverged: {:?} vs {:?}", fast, slow),
+            }
+        }
+
+        Ok(())
+    }
 }
@@ -1,26 +1,478 @@
-#![forbid(unsafe_code)]
+/* Mapping a file takes unsafe code, which only the `mmap` module is allowed. */
+#![cfg_attr(not(feature = "mmap"), forbid(unsafe_code))]
+#![cfg_attr(feature = "mmap", deny(unsafe_code))]
+#![cfg_attr(not(feature = "std"), no_std)]
+/* Parts of the DEFLATE core are only used by the gzip and zlib layers. */
+#![cfg_attr(not(feature = "std"), allow(dead_code))]
 
-use std::io::{BufRead, Write};
+extern crate alloc;
 
-use anyhow::Result;
+use alloc::vec::Vec;
 
-use crate::gzip::GzipReader;
+#[cfg(feature = "std")]
+use std::io::{BufReader, Read, Seek, SeekFrom};
 
+#[cfg(feature = "std")]
+use log::*;
+
+#[cfg(feature = "std")]
+use crate::chunk_writer::ChunkWriter;
+#[cfg(feature = "std")]
+use crate::decompressor::MemberOptions;
+use crate::deflate_writer::DeflateWriter;
+#[cfg(feature = "std")]
+use crate::error::ensure;
+use crate::io::{BufRead, Write};
+#[cfg(feature = "std")]
+use crate::text_writer::TextWriter;
+use crate::tracking_writer::TrackingWriter;
+
+pub use crate::adler32::Adler32;
+#[cfg(feature = "std")]
+pub use crate::bgzf::BgzfReader;
+pub use crate::bit_reader::{BitReader, BitSequence};
+#[cfg(feature = "std")]
+pub use crate::chunk_reader::ChunkReader;
+pub use crate::crc32::crc32_combine;
+#[cfg(feature = "std")]
+pub use crate::decoder::{DecodeState, GzipDecoder, MemberReader};
+#[cfg(feature = "std")]
+pub use crate::decompressor::Decompressor;
+pub use crate::deflate::{BlockHeader, BlockInfo, CompressionType, DeflateReader, DeflateStats};
+pub use crate::deflate_writer::CompressionLevel;
+pub use crate::error::{DecodeError, EofContext, HeaderField, Result};
+#[cfg(feature = "std")]
+pub use crate::gzip::{
+    is_gzip, peek_member_header, CompressionMethod, DeflateLevelHint, ExtraSubField, GzipReader,
+    GzipWriter, HeaderEncoding, MemberFlags, MemberFooter, MemberHeader, MemberInfo, Members,
+    OperatingSystem,
+};
+#[cfg(feature = "mmap")]
+pub use crate::mmap::decompress_mmap;
+#[cfg(feature = "std")]
+pub use crate::parallel::decompress_parallel;
+#[cfg(feature = "std")]
+pub use crate::random_access::{build_index, read_at, AccessPoint, GzipIndex};
+#[cfg(feature = "std")]
+pub use crate::salvage::{decompress_lenient, MemberOutcome};
+#[cfg(feature = "std")]
+pub use crate::zlib::ZlibReader;
+
+mod adler32;
+#[cfg(feature = "std")]
+mod bgzf;
 mod bit_reader;
+mod bit_writer;
+#[cfg(feature = "std")]
+mod chunk_reader;
+#[cfg(feature = "std")]
+mod chunk_writer;
+mod crc32;
+#[cfg(feature = "std")]
+mod decoder;
+#[cfg(feature = "std")]
+mod decompressor;
 mod deflate;
+mod deflate_writer;
+mod error;
+#[cfg(feature = "std")]
 mod gzip;
 mod huffman_coding;
+pub mod io;
+#[cfg(feature = "std")]
+mod limited_writer;
+mod lz77;
+#[cfg(feature = "mmap")]
+mod mmap;
+#[cfg(feature = "std")]
+mod parallel;
+#[cfg(feature = "std")]
+mod progress_writer;
+#[cfg(feature = "std")]
+mod random_access;
+#[cfg(feature = "std")]
+mod salvage;
+#[cfg(feature = "std")]
+mod text_writer;
 mod tracking_writer;
+#[cfg(feature = "std")]
+mod zlib;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Buffer capacity used when the input isn't buffered by the caller.
+#[cfg(feature = "std")]
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(feature = "std")]
+pub fn decompress<R: BufRead, W: Write>(input: R, output: W) -> Result<()> {
+    decompress_with_headers(input, output)?;
+    Ok(())
+}
+
+/// Same as `decompress`, but for unbuffered input such as a `File` or a `TcpStream`.
+#[cfg(feature = "std")]
+pub fn decompress_reader<R: Read, W: Write>(input: R, output: W) -> Result<()> {
+    decompress(BufReader::with_capacity(READ_BUFFER_SIZE, input), output)
+}
+
+/// Same as `decompress`, but also returns the header of every member in the stream.
+#[cfg(feature = "std")]
+pub fn decompress_with_headers<R: BufRead, W: Write>(
+    input: R,
+    output: W,
+) -> Result<Vec<MemberHeader>> {
+    let members = decompress_members(input, output, MemberOptions::default())?;
+    Ok(members.into_iter().map(|info| info.header).collect())
+}
+
+/// Same as `decompress`, but also returns the footer of every member, with the
+/// CRC-32 and size of its data as verified.
+#[cfg(feature = "std")]
+pub fn decompress_with_footers<R: BufRead, W: Write>(
+    input: R,
+    output: W,
+) -> Result<Vec<MemberFooter>> {
+    let members = decompress_members(input, output, MemberOptions::default())?;
+    Ok(members.into_iter().map(|info| info.footer).collect())
+}
+
+/// Same as `decompress`, but data after the last member which can't be the start
+/// of another member is ignored instead of being an error. The unread data is left
+/// in `input`.
+#[cfg(feature = "std")]
+pub fn decompress_allow_trailing<R: BufRead, W: Write>(input: R, output: W) -> Result<()> {
+    Decompressor::new().allow_trailing(true).run(input, output)
+}
+
+/// Check the CRC-32 and ISIZE of every member without keeping the decompressed data,
+/// returning the footers of all the members.
+#[cfg(feature = "std")]
+pub fn verify<R: BufRead>(input: R) -> Result<Vec<MemberFooter>> {
+    let members = decompress_members(input, std::io::sink(), MemberOptions::default())?;
+    Ok(members.into_iter().map(|info| info.footer).collect())
+}
+
+/// Same as `verify`, but returns everything known about each member, including the
+/// number of compressed bytes it occupies, e.g. to index a multi-member stream.
+#[cfg(feature = "std")]
+pub fn index<R: BufRead>(input: R) -> Result<Vec<MemberInfo>> {
+    decompress_members(input, std::io::sink(), MemberOptions::default())
+}
+
+/// Same as `decompress`, but fails if the stream has more than `max_members` members.
+#[cfg(feature = "std")]
+pub fn decompress_max_members<R: BufRead, W: Write>(
+    input: R,
+    output: W,
+    max_members: usize,
+) -> Result<()> {
+    Decompressor::new()
+        .max_members(max_members)
+        .run(input, output)
+}
 
-pub fn decompress<R: BufRead, W: Write>(mut input: R, mut output: W) -> Result<()> {
-    while let Ok(buf) = input.fill_buf() {
+#[cfg(feature = "std")]
+fn decompress_members<R: BufRead, W: Write>(
+    mut input: R,
+    output: W,
+    options: MemberOptions,
+) -> Result<Vec<MemberInfo>> {
+    let mut members = vec![];
+    /* The CRC-32 covers the data as decompressed, so line endings are changed after it. */
+    let output = TextWriter::new(output);
+    let mut writer = if options.verify_crc {
+        TrackingWriter::new(output)
+    } else {
+        TrackingWriter::new_without_crc(output)
+    };
+    /* The magic of the first member is consumed when looking for it, so it's replayed. */
+    let mut prefix: &[u8] = &[];
+    if let Some(max_skip) = options.skip_to_magic {
+        let skipped = gzip::skip_to_magic(&mut input, max_skip)?;
+        info!("skipped {} bytes before the first member", skipped);
+        prefix = &gzip::MAGIC;
+    }
+    let mut input = prefix.chain(input);
+    /* Members follow each other back to back until the input ends. */
+    loop {
+        let buf = input.fill_buf()?;
         if buf.is_empty() {
             break;
         }
-        let gz_reader = GzipReader::new(input);
-        let (new_input, new_output) = gz_reader.decompress(output)?;
+        /*
+         * Only the first byte after a completed member can be inspected without
+         * consuming anything: `fill_buf` may return a single byte. So a byte other
+         * than ID1 there is taken as trailing data, while ID1 commits to parsing
+         * another member, whose errors (including a wrong ID2 or a truncated
+         * header) are reported as usual. The first member is always required.
+         */
+        if options.allow_trailing && !members.is_empty() && buf[0] != gzip::ID1 {
+            info!("ignoring trailing data after the last member");
+            break;
+        }
+        ensure!(
+            members.len() < options.max_members,
+            DecodeError::TooManyMembers
+        );
+        let mut gz_reader = GzipReader::new(input).strict_padding(options.strict_padding);
+        if let Some(capacity) = options.tree_cache {
+            gz_reader = gz_reader.tree_cache(capacity);
+        }
+        if options.normalize_line_endings {
+            let header = gz_reader.read_header()?;
+            writer.get_mut().set_enabled(header.is_text)?;
+        }
+        let (info, new_input) =
+            gz_reader.decompress_member_into(&mut writer, options.verify_size)?;
+        members.push(info);
         input = new_input;
-        output = new_output;
     }
+    writer.get_mut().finish()?;
+    Ok(members)
+}
+
+/// Size of the chunks handed out by `decompress_windowed`.
+#[cfg(feature = "std")]
+const WINDOWED_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Same as `decompress`, but calls `sink` with every chunk of decoded data, of at most
+/// 16 KiB, e.g. to feed a hasher. Besides the 32 KiB window of back-references, only
+/// the current chunk is kept in memory.
+#[cfg(feature = "std")]
+pub fn decompress_windowed<R: BufRead, F: FnMut(&[u8])>(input: R, sink: F) -> Result<()> {
+    let mut writer = ChunkWriter::new(sink, WINDOWED_CHUNK_SIZE);
+    decompress(input, &mut writer)?;
+    writer.finish();
     Ok(())
 }
+
+/// Same as `decompress`, but fails as soon as the output of all members combined
+/// would exceed `max_bytes`.
+#[cfg(feature = "std")]
+pub fn decompress_limited<R: BufRead, W: Write>(input: R, output: W, max_bytes: u64) -> Result<()> {
+    Decompressor::new().max_output(max_bytes).run(input, output)
+}
+
+/// Same as `decompress`, but calls `callback` with the number of bytes decompressed
+/// so far after every 64 KiB of output and once more when done. The callback isn't
+/// called again after an error.
+#[cfg(feature = "std")]
+pub fn decompress_with_progress<R: BufRead, W: Write, F: FnMut(u64)>(
+    input: R,
+    output: W,
+    callback: F,
+) -> Result<()> {
+    Decompressor::new().on_progress(callback).run(input, output)
+}
+
+/// Decompress the whole `input` into a freshly allocated buffer. The input is decoded
+/// as it is read, and the output is allocated up front from the ISIZE at the end of
+/// the input already buffered by `input`, which is exact for a single member whose
+/// footer is in the buffer, e.g. in a slice. See `size_hint` for other inputs.
+#[cfg(feature = "std")]
+pub fn decompress_to_vec<R: BufRead>(mut input: R) -> Result<Vec<u8>> {
+    let size_hint = size_hint(input.fill_buf()?);
+    decompress_presized(input, size_hint)
+}
+
+/// Same as `decompress_to_vec`, but the ISIZE of the last member is found by seeking
+/// to the end of `input`, so that it needn't be in the reader's buffer.
+#[cfg(feature = "std")]
+pub fn decompress_seekable_to_vec<R: BufRead + Seek>(mut input: R) -> Result<Vec<u8>> {
+    let size_hint = seek_size_hint(&mut input)?;
+    decompress_presized(input, size_hint)
+}
+
+/// Decompress every member of an in-memory gzip stream, without copying the input.
+/// A memory-mapped file, such as a `memmap2::Mmap`, can be passed as is, see also
+/// `decompress_mmap`.
+#[cfg(feature = "std")]
+pub fn decompress_slice(input: &[u8]) -> Result<Vec<u8>> {
+    decompress_presized(input, size_hint(input))
+}
+
+/// Decompress as much of `input` as possible. Unlike `decompress_to_vec`, the data
+/// decoded before an error is returned along with it, e.g. for fuzzing.
+#[cfg(feature = "std")]
+pub fn decompress_from_slice(input: &[u8]) -> (Vec<u8>, Result<()>) {
+    let mut output = vec![];
+    let result = decompress(input, &mut output);
+    (output, result)
+}
+
+/// Guess the decompressed size from the ISIZE field of the last member, which is exact
+/// for single-member streams. The guess is bounded by the maximal DEFLATE ratio so that
+/// a forged footer can't trigger a huge allocation.
+#[cfg(feature = "std")]
+fn size_hint(compressed: &[u8]) -> usize {
+    match compressed.len().checked_sub(4) {
+        Some(pos) => {
+            let isize = u32::from_le_bytes(compressed[pos..].try_into().unwrap());
+            bounded_size_hint(isize, compressed.len() as u64)
+        }
+        None => 0,
+    }
+}
+
+/// Same as `size_hint`, but seeks to the ISIZE field at the end of `input` and back
+/// instead of needing the whole stream in memory.
+#[cfg(feature = "std")]
+fn seek_size_hint<R: Read + Seek>(input: &mut R) -> Result<usize> {
+    let start = input.stream_position()?;
+    let end = input.seek(SeekFrom::End(0))?;
+    let hint = match end.checked_sub(start) {
+        Some(len) if len >= 4 => {
+            input.seek(SeekFrom::End(-4))?;
+            let mut isize = [0; 4];
+            input.read_exact(&mut isize)?;
+            bounded_size_hint(u32::from_le_bytes(isize), len)
+        }
+        _ => 0,
+    };
+    input.seek(SeekFrom::Start(start))?;
+    Ok(hint)
+}
+
+#[cfg(feature = "std")]
+fn bounded_size_hint(isize: u32, compressed_len: u64) -> usize {
+    const MAX_RATIO: u64 = 1032;
+    let bound = compressed_len.saturating_mul(MAX_RATIO);
+    usize::try_from(u64::from(isize).min(bound)).unwrap_or(usize::MAX)
+}
+
+/// Most output allocated up front from a size hint. With several members, ISIZE is
+/// only the size of the last one, so the rest of the hint is only allocated once the
+/// output gets there.
+#[cfg(feature = "std")]
+const MAX_SIZE_HINT: usize = 64 << 20;
+
+/// Decompress `input` into a buffer allocated for `size_hint` bytes, of which only
+/// `MAX_SIZE_HINT` up front.
+#[cfg(feature = "std")]
+fn decompress_presized<R: BufRead>(input: R, size_hint: usize) -> Result<Vec<u8>> {
+    let mut output = PresizedVec {
+        data: Vec::with_capacity(size_hint.min(MAX_SIZE_HINT)),
+        size_hint,
+    };
+    decompress(input, &mut output)?;
+    Ok(output.data)
+}
+
+/// A `Vec` growing to the size hint at once when it is full, and as usual from there.
+#[cfg(feature = "std")]
+struct PresizedVec {
+    data: Vec<u8>,
+    size_hint: usize,
+}
+
+#[cfg(feature = "std")]
+impl Write for PresizedVec {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.data.len() + buf.len();
+        if len > self.data.capacity() && self.size_hint > self.data.capacity() {
+            self.data
+                .reserve_exact(self.size_hint.max(len) - self.data.len());
+        }
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Decompress a raw DEFLATE stream without gzip framing, returning the number of bytes
+/// written. The input is left right after the final block.
+pub fn inflate_raw<R: BufRead, W: Write>(input: R, output: W) -> Result<u64> {
+    let mut deflate_reader = DeflateReader::new(BitReader::new(input));
+    let (byte_count, _) = deflate_reader.deflate(output)?;
+    Ok(byte_count)
+}
+
+/// Same as `inflate_raw`, but for a stream compressed with a window of `window_size`
+/// bytes (a power of two from 256 to 32768), which limits the memory used. Streams
+/// referring further back are rejected.
+pub fn inflate_raw_with_window_size<R: BufRead, W: Write>(
+    input: R,
+    output: W,
+    window_size: usize,
+) -> Result<u64> {
+    let mut deflate_reader = DeflateReader::new(BitReader::new(input)).window_size(window_size);
+    let (byte_count, _) = deflate_reader.deflate(output)?;
+    Ok(byte_count)
+}
+
+/// Same as `inflate_raw`, but for a raw DEFLATE64 stream, as found in some zip archives.
+pub fn inflate64_raw<R: BufRead, W: Write>(input: R, output: W) -> Result<u64> {
+    let mut deflate_reader = DeflateReader::new_deflate64(BitReader::new(input));
+    let (byte_count, _) = deflate_reader.deflate(output)?;
+    Ok(byte_count)
+}
+
+/// Same as `inflate_raw`, but returns statistics of the decoded stream.
+pub fn inflate_raw_with_stats<R: BufRead, W: Write>(input: R, output: W) -> Result<DeflateStats> {
+    let mut deflate_reader = DeflateReader::new(BitReader::new(input));
+    let mut writer = TrackingWriter::new(output);
+    deflate_reader.deflate_tracked(&mut writer)?;
+    Ok(deflate_reader.stats())
+}
+
+/// Same as `inflate_raw`, but returns where each block starts and what its header
+/// says, for debugging.
+pub fn inflate_raw_with_blocks<R: BufRead, W: Write>(
+    input: R,
+    output: W,
+) -> Result<Vec<BlockInfo>> {
+    let mut deflate_reader = DeflateReader::new(BitReader::new(input)).record_blocks();
+    let mut writer = TrackingWriter::new(output);
+    deflate_reader.deflate_tracked(&mut writer)?;
+    Ok(deflate_reader.blocks().to_vec())
+}
+
+/// Compress `input` into a raw DEFLATE stream without gzip framing.
+pub fn deflate_raw<W: Write>(input: &[u8], output: W, level: CompressionLevel) -> Result<()> {
+    let mut deflate_writer = DeflateWriter::new(output, level);
+    deflate_writer.write_block(input, true)?;
+    deflate_writer.into_inner()?;
+    Ok(())
+}
+
+/// Decompress a zlib (RFC 1950) stream.
+#[cfg(feature = "std")]
+pub fn decompress_zlib<R: BufRead, W: Write>(input: R, output: W) -> Result<()> {
+    ZlibReader::new(input).decompress(output)?;
+    Ok(())
+}
+
+/// Same as `decompress_zlib`, but streams may reference the preset `dictionary`.
+#[cfg(feature = "std")]
+pub fn decompress_zlib_with_dictionary<R: BufRead, W: Write>(
+    input: R,
+    output: W,
+    dictionary: &[u8],
+) -> Result<()> {
+    ZlibReader::with_dictionary(input, dictionary).decompress(output)?;
+    Ok(())
+}
+
+/// Compress `input` into a gzip stream of a single member.
+#[cfg(feature = "std")]
+pub fn compress<R: BufRead, W: Write>(input: R, output: W, level: CompressionLevel) -> Result<()> {
+    let header = MemberHeader {
+        os: OperatingSystem::Unknown.into(),
+        ..Default::default()
+    };
+    GzipWriter::new(output, header).compress(input, level)?;
+    Ok(())
+}
+
+/// Wrap `data` into a gzip stream of a single member without compressing it: the
+/// data is split into stored blocks of at most 65535 bytes.
+#[cfg(feature = "std")]
+pub fn store<W: Write>(data: &[u8], output: W) -> Result<()> {
+    compress(data, output, CompressionLevel::Store)
+}
@@ -0,0 +1,94 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, Write};
+
+use crate::error::DecodeError;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Writer that refuses to pass more than `limit` bytes to the inner writer. The error
+/// is an `OutputLimitExceeded` passed as an I/O error, see `unwrap_limit_error`.
+pub struct LimitedWriter<T> {
+    inner: T,
+    limit: u64,
+    byte_count: u64,
+}
+
+impl<T: Write> Write for LimitedWriter<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.byte_count + buf.len() as u64 > self.limit {
+            return Err(io::Error::other(DecodeError::OutputLimitExceeded {
+                limit: self.limit,
+                written: self.byte_count,
+            }));
+        }
+        let written_len = self.inner.write(buf)?;
+        self.byte_count += written_len as u64;
+        Ok(written_len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Write> LimitedWriter<T> {
+    pub fn new(inner: T, limit: u64) -> Self {
+        Self {
+            inner,
+            limit,
+            byte_count: 0,
+        }
+    }
+}
+
+/// Same as `err`, unless it is the `OutputLimitExceeded` of a `LimitedWriter` passed
+/// as an I/O error, which is unwrapped.
+pub fn unwrap_limit_error(err: DecodeError) -> DecodeError {
+    match err {
+        DecodeError::Io(err) => match err.get_ref().and_then(|inner| inner.downcast_ref()) {
+            Some(&DecodeError::OutputLimitExceeded { limit, written }) => {
+                DecodeError::OutputLimitExceeded { limit, written }
+            }
+            _ => DecodeError::Io(err),
+        },
+        err => err,
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write() -> io::Result<()> {
+        let mut writer = LimitedWriter::new(vec![], 10);
+
+        writer.write_all(&[1, 2, 3, 4])?;
+        let err = writer.write_all(&[5, 6, 7, 8, 9, 10, 11]).unwrap_err();
+        assert!(matches!(
+            unwrap_limit_error(err.into()),
+            DecodeError::OutputLimitExceeded {
+                limit: 10,
+                written: 4
+            }
+        ));
+        writer.write_all(&[5, 6, 7, 8, 9, 10])?;
+        assert_eq!(writer.byte_count, 10);
+
+        let err = writer.write_all(&[11]).unwrap_err();
+        assert!(matches!(
+            unwrap_limit_error(err.into()),
+            DecodeError::OutputLimitExceeded {
+                limit: 10,
+                written: 10
+            }
+        ));
+        assert_eq!(writer.byte_count, 10);
+        assert_eq!(writer.inner, (1..=10).collect::<Vec<u8>>());
+
+        Ok(())
+    }
+}
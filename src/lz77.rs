@@ -0,0 +1,207 @@
+#![forbid(unsafe_code)]
+
+use alloc::{vec, vec::Vec};
+
+////////////////////////////////////////////////////////////////////////////////
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+const HASH_BITS: u32 = 15;
+/// Number of earlier positions tried before settling for the longest match so far.
+const MAX_CHAIN: usize = 128;
+const NIL: usize = usize::MAX;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Token {
+    Literal(u8),
+    Match { len: u16, dist: u16 },
+}
+
+/// Greedy LZ77 matcher over a 32 KiB window, finding matches through hash chains of
+/// 3-byte prefixes. The window spans calls to `tokenize`, so matches may reach into
+/// data passed earlier.
+pub struct Matcher {
+    /// The tail of the earlier data followed by the data being tokenized.
+    window: Vec<u8>,
+    /// Position of `window[0]` in the whole stream.
+    offset: usize,
+    /// Latest stream position of every prefix hash.
+    head: Vec<usize>,
+    /// Previous stream position with the same hash, indexed modulo the window size.
+    prev: Vec<usize>,
+    /// Stream position up to which prefixes are inserted into the chains.
+    inserted: usize,
+}
+
+impl Matcher {
+    pub fn new() -> Self {
+        Self {
+            window: Vec::new(),
+            offset: 0,
+            head: vec![NIL; 1 << HASH_BITS],
+            prev: vec![NIL; WINDOW_SIZE],
+            inserted: 0,
+        }
+    }
+
+    /// Split `data` into literals and matches, appending them to `tokens`.
+    pub fn tokenize(&mut self, data: &[u8], tokens: &mut Vec<Token>) {
+        self.slide();
+        let mut pos = self.window.len();
+        self.window.extend_from_slice(data);
+
+        while pos < self.window.len() {
+            self.insert_until(pos);
+            match self.longest_match(pos) {
+                Some((len, dist)) => {
+                    tokens.push(Token::Match {
+                        len: len as u16,
+                        dist: dist as u16,
+                    });
+                    pos += len;
+                }
+                None => {
+                    tokens.push(Token::Literal(self.window[pos]));
+                    pos += 1;
+                }
+            }
+        }
+        self.insert_until(pos);
+    }
+
+    /// Drop the data which is too far behind to be matched anymore.
+    fn slide(&mut self) {
+        if self.window.len() > WINDOW_SIZE {
+            let excess = self.window.len() - WINDOW_SIZE;
+            self.window.drain(..excess);
+            self.offset += excess;
+        }
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let prefix = u32::from(self.window[pos]) << 16
+            | u32::from(self.window[pos + 1]) << 8
+            | u32::from(self.window[pos + 2]);
+        (prefix.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+    }
+
+    /// Insert the prefixes starting before window position `end` into the chains,
+    /// except for the last ones which aren't 3 bytes long yet.
+    fn insert_until(&mut self, end: usize) {
+        self.inserted = self.inserted.max(self.offset);
+        while self.inserted < self.offset + end
+            && self.inserted - self.offset + MIN_MATCH <= self.window.len()
+        {
+            let hash = self.hash(self.inserted - self.offset);
+            self.prev[self.inserted % WINDOW_SIZE] = self.head[hash];
+            self.head[hash] = self.inserted;
+            self.inserted += 1;
+        }
+    }
+
+    /// Longest match for the data at window position `pos`, as a length and a distance.
+    fn longest_match(&self, pos: usize) -> Option<(usize, usize)> {
+        if pos + MIN_MATCH > self.window.len() {
+            return None;
+        }
+        let current = self.offset + pos;
+        let max_len = MAX_MATCH.min(self.window.len() - pos);
+        let mut best: Option<(usize, usize)> = None;
+
+        let mut candidate = self.head[self.hash(pos)];
+        for _ in 0..MAX_CHAIN {
+            if candidate == NIL || current - candidate > WINDOW_SIZE {
+                break;
+            }
+            let start = candidate - self.offset;
+            let len = self.window[start..start + max_len]
+                .iter()
+                .zip(&self.window[pos..pos + max_len])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if len >= MIN_MATCH && best.is_none_or(|(best_len, _)| len > best_len) {
+                best = Some((len, current - candidate));
+                if len == max_len {
+                    break;
+                }
+            }
+
+            /* A later position with the same index means the chain is overwritten. */
+            let next = self.prev[candidate % WINDOW_SIZE];
+            if next >= candidate {
+                break;
+            }
+            candidate = next;
+        }
+        best
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(tokens: &[Token], output: &mut Vec<u8>) {
+        for token in tokens {
+            match *token {
+                Token::Literal(byte) => output.push(byte),
+                Token::Match { len, dist } => {
+                    for _ in 0..len {
+                        output.push(output[output.len() - usize::from(dist)]);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tokenize() {
+        let mut matcher = Matcher::new();
+        let mut tokens = vec![];
+        matcher.tokenize(b"abcabcabcabcx", &mut tokens);
+        assert_eq!(
+            tokens,
+            [
+                Token::Literal(b'a'),
+                Token::Literal(b'b'),
+                Token::Literal(b'c'),
+                Token::Match { len: 9, dist: 3 },
+                Token::Literal(b'x'),
+            ]
+        );
+
+        tokens.clear();
+        matcher.tokenize(b"xabcab", &mut tokens);
+        assert_eq!(
+            tokens,
+            [Token::Literal(b'x'), Token::Match { len: 5, dist: 8 }]
+        );
+    }
+
+    #[test]
+    fn tokenize_round_trip() {
+        let data: Vec<u8> = (0..300_000u32)
+            .map(|i| ((i % 251) ^ (i / 4096)) as u8)
+            .collect();
+        let mut matcher = Matcher::new();
+        let mut tokens = vec![];
+        for chunk in data.chunks(65535) {
+            matcher.tokenize(chunk, &mut tokens);
+        }
+
+        let mut output = vec![];
+        expand(&tokens, &mut output);
+        assert_eq!(output, data);
+        assert!(tokens.len() < data.len() / 2);
+        for token in tokens {
+            if let Token::Match { len, dist } = token {
+                assert!((3..=258).contains(&len));
+                assert!((1..=32768).contains(&dist));
+            }
+        }
+    }
+}
@@ -0,0 +1,24 @@
+#![deny(unsafe_code)]
+
+use std::{fs::File, io::Write, path::Path};
+
+use memmap2::Mmap;
+
+use crate::error::Result;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Decompress every member of the gzip file at `path` into `output`. The file is
+/// memory-mapped rather than read, so the compressed data isn't copied into memory.
+/// The file must not be changed by anyone while it is decompressed.
+pub fn decompress_mmap<W: Write>(path: &Path, output: W) -> Result<()> {
+    let file = File::open(path)?;
+    /*
+     * Safety: the mapping is only read from, and only lives until the end of this call.
+     * It is sound as long as no one writes to or truncates the file meanwhile, which is
+     * left to the caller, as documented.
+     */
+    #[allow(unsafe_code)]
+    let map = unsafe { Mmap::map(&file)? };
+    crate::decompress(&map[..], output)
+}
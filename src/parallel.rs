@@ -0,0 +1,426 @@
+#![forbid(unsafe_code)]
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, ErrorKind, Read, Write};
+use std::mem;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Mutex;
+use std::thread;
+
+use log::*;
+
+use crate::error::{DecodeError, Result};
+use crate::gzip::{GzipReader, HeaderEncoding, FOOTER_LEN, ID1, MAGIC};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Amount of input read at once while looking for member boundaries.
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Length of the shortest deflate stream: a fixed block with only the end of block.
+const MIN_DEFLATE_LEN: u64 = 2;
+
+/// Most bytes of data a byte of deflate stream can encode: a length of 258 and a
+/// distance, of a bit each.
+const MAX_DEFLATE_RATIO: u64 = 258 * 4;
+
+/// Bits of FLG which are reserved, and so are zero in a valid header.
+const RESERVED_FLAGS: u8 = 0xe0;
+
+/// Number of segments of input being decoded or waiting to be written, per thread.
+const PENDING_PER_THREAD: usize = 2;
+
+/// Same as `decompress`, but decodes the members on `threads` threads at once.
+///
+/// Where a member ends is only known once it is decoded, so `input` is read forward,
+/// without seeking, and split into segments before every ID1, ID2 and CM of deflate
+/// that follows a plausible footer and starts a valid header. Each segment is decoded on its own, and if a member
+/// turns out to go on past the end of its segment, the split was inside of it: the
+/// member is decoded from there on along with the next segments, whose own results are
+/// dropped. Every member is read into memory as a whole, but only a couple of
+/// segments per thread are read ahead and kept until written.
+pub fn decompress_parallel<R: Read, W: Write>(
+    input: R,
+    mut output: W,
+    threads: usize,
+) -> Result<()> {
+    let threads = threads.max(1);
+    let (jobs, job_receiver) = mpsc::sync_channel::<Job>(threads);
+    let job_receiver = Mutex::new(job_receiver);
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let job_receiver = &job_receiver;
+            scope.spawn(move || run_worker(job_receiver, decode_segment));
+        }
+        let mut pipeline = Pipeline {
+            splitter: Splitter::new(input),
+            jobs,
+            pending: VecDeque::new(),
+            capacity: threads * PENDING_PER_THREAD,
+        };
+        write_in_order(&mut pipeline, &mut output)
+    })
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+struct Job {
+    segment: Vec<u8>,
+    reply: SyncSender<DecodedSegment>,
+}
+
+/// Decode the segments of the jobs from `jobs` with `decode` until they are all
+/// handed out.
+fn run_worker(jobs: &Mutex<Receiver<Job>>, decode: impl Fn(Vec<u8>) -> DecodedSegment) {
+    loop {
+        /* The lock is released before decoding, so that the other workers take jobs. */
+        let job = jobs.lock().unwrap().recv();
+        /* Fails once all the segments are handed out. */
+        let Ok(job) = job else {
+            break;
+        };
+        /* Fails if the result isn't needed anymore. */
+        let _ = job.reply.send(decode(job.segment));
+    }
+}
+
+/// The members of a segment of input, decoded up to the first error.
+struct DecodedSegment {
+    segment: Vec<u8>,
+    /// Data of the members decoded without an error.
+    output: Vec<u8>,
+    /// Length of the members decoded without an error.
+    consumed: usize,
+    result: Result<()>,
+}
+
+fn decode_segment(segment: Vec<u8>) -> DecodedSegment {
+    let mut output = vec![];
+    let mut rest = segment.as_slice();
+    let mut result = Ok(());
+    while !rest.is_empty() {
+        let len = output.len();
+        match GzipReader::new(rest).decompress_one(&mut output) {
+            Ok((new_rest, ..)) => rest = new_rest,
+            Err(err) => {
+                output.truncate(len);
+                result = Err(err);
+                break;
+            }
+        }
+    }
+    DecodedSegment {
+        consumed: segment.len() - rest.len(),
+        segment,
+        output,
+        result,
+    }
+}
+
+/// Write the members from the start of the input on, taking the decoded segments
+/// from `pipeline`.
+fn write_in_order<R: Read, W: Write>(pipeline: &mut Pipeline<R>, output: &mut W) -> Result<()> {
+    while let Some(decoded) = pipeline.next_segment()? {
+        output.write_all(&decoded.output)?;
+        match decoded.result {
+            Ok(()) => {}
+            Err(DecodeError::UnexpectedEof { .. }) => {
+                debug!("a member goes on after the end of its segment");
+                let mut reader = SegmentReader {
+                    pipeline,
+                    segment: decoded.segment,
+                    pos: decoded.consumed,
+                };
+                /* Until a member ends where a segment does, so that segments are in step. */
+                loop {
+                    let (_, member, _) = GzipReader::new(&mut reader).decompress_one(Vec::new())?;
+                    output.write_all(&member)?;
+                    if reader.pos == reader.segment.len() {
+                        break;
+                    }
+                }
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Hands out the segments of the input in order, decoded on the worker threads.
+struct Pipeline<R> {
+    splitter: Splitter<R>,
+    jobs: SyncSender<Job>,
+    /// Results of the segments handed to the workers, in order.
+    pending: VecDeque<Receiver<DecodedSegment>>,
+    capacity: usize,
+}
+
+impl<R: Read> Pipeline<R> {
+    /// The next segment, once decoded, or `None` at the end of the input.
+    fn next_segment(&mut self) -> io::Result<Option<DecodedSegment>> {
+        while self.pending.len() < self.capacity {
+            let Some(segment) = self.splitter.next_segment()? else {
+                break;
+            };
+            let (reply, receiver) = mpsc::sync_channel(1);
+            self.jobs
+                .send(Job { segment, reply })
+                .map_err(|_| worker_panicked())?;
+            self.pending.push_back(receiver);
+        }
+        match self.pending.pop_front() {
+            Some(receiver) => receiver.recv().map(Some).map_err(|_| worker_panicked()),
+            None => Ok(None),
+        }
+    }
+}
+
+fn worker_panicked() -> io::Error {
+    io::Error::other("a decoding thread has panicked")
+}
+
+/// Reads the rest of a segment and then the following ones, dropping their results.
+struct SegmentReader<'a, R> {
+    pipeline: &'a mut Pipeline<R>,
+    segment: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> Read for SegmentReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.fill_buf()?.read(buf)?;
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: Read> BufRead for SegmentReader<'_, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.segment.len() {
+            if let Some(decoded) = self.pipeline.next_segment()? {
+                self.segment = decoded.segment;
+                self.pos = 0;
+            }
+        }
+        Ok(&self.segment[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Splits the input at the likely starts of members.
+struct Splitter<R> {
+    input: R,
+    /// Input read from the start of the current segment on.
+    buf: Vec<u8>,
+    /// Length of the header at the start of `buf`, once known.
+    header_len: Option<u64>,
+    /// Where to look for the next split in `buf`.
+    search_pos: usize,
+    eof: bool,
+}
+
+enum Split {
+    /// With the length of the header there.
+    Yes(u64),
+    No,
+    /// The header may be valid, but goes on past the input read so far.
+    NeedMore,
+}
+
+impl<R: Read> Splitter<R> {
+    fn new(input: R) -> Self {
+        Self {
+            input,
+            buf: vec![],
+            header_len: None,
+            search_pos: 1,
+            eof: false,
+        }
+    }
+
+    fn next_segment(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            while let Some(pos) = self.find_magic() {
+                match self.check_split(pos) {
+                    Split::Yes(header_len) => {
+                        trace!("splitting the input before a {} bytes header", header_len);
+                        let rest = self.buf.split_off(pos);
+                        self.header_len = Some(header_len);
+                        self.search_pos = 1;
+                        return Ok(Some(mem::replace(&mut self.buf, rest)));
+                    }
+                    Split::No => self.search_pos = pos + 1,
+                    Split::NeedMore => break,
+                }
+            }
+            if self.eof {
+                return Ok((!self.buf.is_empty()).then(|| mem::take(&mut self.buf)));
+            }
+            self.read_chunk()?;
+        }
+    }
+
+    /// Position of the next ID1 from `search_pos` on.
+    fn find_magic(&self) -> Option<usize> {
+        let start = self.search_pos.min(self.buf.len());
+        self.buf[start..]
+            .iter()
+            .position(|&byte| byte == ID1)
+            .map(|pos| start + pos)
+    }
+
+    /// Whether a member can start at `pos`: a valid header starts there, and the data
+    /// before it can end with a footer, which is only checked for the length of the
+    /// deflate stream it implies.
+    fn check_split(&mut self, pos: usize) -> Split {
+        let candidate = &self.buf[pos..];
+        if candidate.len() < MAGIC.len() && !self.eof {
+            return Split::NeedMore;
+        }
+        let flags = candidate.get(MAGIC.len()).copied().unwrap_or(0);
+        if !candidate.starts_with(&MAGIC) || flags & RESERVED_FLAGS != 0 {
+            return Split::No;
+        }
+        /* Other members may come before the one ending here, so this can only reject. */
+        let header_len = *self
+            .header_len
+            .get_or_insert_with(|| parse_header_len(&self.buf).unwrap_or(0));
+        let deflate_len = (pos as u64).saturating_sub(header_len + FOOTER_LEN);
+        let Some(data_size) = self.buf[..pos].last_chunk::<4>() else {
+            return Split::No;
+        };
+        let data_size = u64::from(u32::from_le_bytes(*data_size));
+        if deflate_len < MIN_DEFLATE_LEN || data_size > deflate_len * MAX_DEFLATE_RATIO {
+            return Split::No;
+        }
+        match GzipReader::parse_header_counted(&mut &self.buf[pos..], HeaderEncoding::Latin1) {
+            Ok((_, _, len)) => Split::Yes(len),
+            Err(DecodeError::UnexpectedEof { .. }) if !self.eof => Split::NeedMore,
+            Err(_) => Split::No,
+        }
+    }
+
+    fn read_chunk(&mut self) -> io::Result<()> {
+        let len = self.buf.len();
+        self.buf.resize(len + READ_CHUNK_SIZE, 0);
+        let result = loop {
+            match self.input.read(&mut self.buf[len..]) {
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                result => break result,
+            }
+        };
+        let read_len = *result.as_ref().unwrap_or(&0);
+        self.buf.truncate(len + read_len);
+        self.eof = read_len == 0;
+        result.map(|_| ())
+    }
+}
+
+/// Length of the header at the start of `bytes`, if it is valid.
+fn parse_header_len(mut bytes: &[u8]) -> Option<u64> {
+    GzipReader::parse_header_counted(&mut bytes, HeaderEncoding::Latin1)
+        .ok()
+        .map(|(_, _, len)| len)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment_lens(data: &[u8]) -> Vec<usize> {
+        let mut splitter = Splitter::new(data);
+        let mut lens = vec![];
+        while let Some(segment) = splitter.next_segment().unwrap() {
+            lens.push(segment.len());
+        }
+        lens
+    }
+
+    #[test]
+    fn splits_between_members() {
+        let first: &[u8] = include_bytes!("../data/ok/01-page.gz");
+        let second: &[u8] = include_bytes!("../data/ok/00-Cargo.toml.gz");
+        let data = [first, second, first].concat();
+        assert_eq!(
+            segment_lens(&data),
+            [first.len(), second.len(), first.len()]
+        );
+        assert!(segment_lens(&[]).is_empty());
+    }
+
+    #[test]
+    fn no_split_inside_header() {
+        /* A gzip file stored in another one, which has the magic at 0 and 15. */
+        let data: &[u8] = include_bytes!("../data/ok/07-war-and-peace.txt.gz.gz");
+        assert_eq!(data[15..18], MAGIC);
+        assert_eq!(segment_lens(data), [data.len()]);
+    }
+
+    #[test]
+    fn no_split_after_impossible_footer() {
+        /* Too close to the header for a footer, then after a too large ISIZE. */
+        let mut data = vec![];
+        let header = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x00\xff";
+        crate::store(header, &mut data).unwrap();
+        let first_len = data.len();
+        crate::store(&[&[0xff; 4][..], header].concat(), &mut data).unwrap();
+        assert_eq!(segment_lens(&data), [first_len, data.len() - first_len]);
+    }
+
+    #[test]
+    fn workers_decode_at_once() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::{Duration, Instant};
+
+        /* Each job waits for the other one to be decoded at the same time. */
+        let decoding = AtomicUsize::new(0);
+        let decode = |segment: Vec<u8>| {
+            decoding.fetch_add(1, Ordering::SeqCst);
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while decoding.load(Ordering::SeqCst) < 2 && Instant::now() < deadline {
+                thread::yield_now();
+            }
+            let result = match decoding.load(Ordering::SeqCst) {
+                2 => Ok(()),
+                _ => Err(io::Error::other("decoded alone").into()),
+            };
+            DecodedSegment {
+                segment,
+                output: vec![],
+                consumed: 0,
+                result,
+            }
+        };
+
+        let (jobs, job_receiver) = mpsc::sync_channel(2);
+        let job_receiver = Mutex::new(job_receiver);
+        let mut receivers = vec![];
+        for _ in 0..2 {
+            let (reply, receiver) = mpsc::sync_channel(1);
+            jobs.send(Job {
+                segment: vec![],
+                reply,
+            })
+            .unwrap();
+            receivers.push(receiver);
+        }
+        drop(jobs);
+        thread::scope(|scope| {
+            for _ in 0..2 {
+                scope.spawn(|| run_worker(&job_receiver, decode));
+            }
+        });
+        for receiver in receivers {
+            receiver.recv().unwrap().result.unwrap();
+        }
+    }
+}
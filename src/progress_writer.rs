@@ -0,0 +1,75 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, Write};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Writer that reports the total number of bytes written to `callback` every
+/// time it crosses a multiple of `interval`.
+pub struct ProgressWriter<T, F> {
+    inner: T,
+    callback: F,
+    interval: u64,
+    byte_count: u64,
+}
+
+impl<T: Write, F: FnMut(u64)> Write for ProgressWriter<T, F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written_len = self.inner.write(buf)?;
+        let prev_count = self.byte_count;
+        self.byte_count += written_len as u64;
+        if prev_count / self.interval != self.byte_count / self.interval {
+            (self.callback)(self.byte_count);
+        }
+        Ok(written_len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Write, F: FnMut(u64)> ProgressWriter<T, F> {
+    pub fn new(inner: T, interval: u64, callback: F) -> Self {
+        assert!(interval != 0);
+        Self {
+            inner,
+            callback,
+            interval,
+            byte_count: 0,
+        }
+    }
+
+    /// Report the final byte count, unless it has just been reported.
+    pub fn finish(mut self) -> T {
+        if !self.byte_count.is_multiple_of(self.interval) {
+            (self.callback)(self.byte_count);
+        }
+        self.inner
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write() -> io::Result<()> {
+        let mut reported = vec![];
+        let mut writer = ProgressWriter::new(vec![], 4, |count| reported.push(count));
+
+        writer.write_all(&[1, 2, 3])?;
+        writer.write_all(&[4])?;
+        writer.write_all(&[5, 6, 7, 8, 9, 10])?;
+        writer.write_all(&[11, 12])?;
+        writer.write_all(&[13])?;
+        let output = writer.finish();
+
+        assert_eq!(output, (1..=13).collect::<Vec<u8>>());
+        assert_eq!(reported, [4, 10, 12, 13]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, BufRead, Seek, SeekFrom, Write};
+
+use log::*;
+
+use crate::{
+    bit_reader::BitReader,
+    deflate::DeflateReader,
+    error::Result,
+    gzip::{GzipReader, HeaderEncoding, MemberFooter},
+    tracking_writer::TrackingWriter,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Amount of output decoded at once by `read_at`.
+const CHUNK_SIZE: usize = 32 * 1024;
+
+/// A position at a block boundary of the deflate stream, from which decoding can
+/// start over given the window of data before it.
+#[derive(Clone, Debug)]
+pub struct AccessPoint {
+    /// Position in the input, in bits from the start of the gzip member.
+    pub bit_offset: u64,
+    /// Position in the decompressed data.
+    pub offset: u64,
+    /// Up to 32 KiB of decompressed data right before `offset`.
+    window: Vec<u8>,
+}
+
+/// Access points into the first member of a gzip stream, see `build_index`.
+#[derive(Clone, Debug)]
+pub struct GzipIndex {
+    points: Vec<AccessPoint>,
+    /// Size of the decompressed data of the member.
+    pub len: u64,
+}
+
+impl GzipIndex {
+    /// The access points, by increasing offset. The first one is at the start of
+    /// the data.
+    pub fn points(&self) -> &[AccessPoint] {
+        &self.points
+    }
+}
+
+/// Decompress the first member of `input`, keeping an access point at the first block
+/// boundary after every `span` bytes of data, so that `read_at` can decode from there
+/// rather than from the start. Each access point takes up to 32 KiB of memory.
+pub fn build_index<R: BufRead>(mut input: R, span: u64) -> Result<GzipIndex> {
+    let (_, _, header_len) = GzipReader::parse_header_counted(&mut input, HeaderEncoding::Latin1)?;
+    let mut deflate_reader = DeflateReader::new(BitReader::new(&mut input));
+    let mut writer = TrackingWriter::new(io::sink());
+    let mut points = vec![AccessPoint {
+        bit_offset: header_len * 8,
+        offset: 0,
+        window: vec![],
+    }];
+    while deflate_reader.decode_block(&mut writer)? {
+        let last_offset = points.last().map_or(0, |point| point.offset);
+        if writer.byte_count() - last_offset >= span {
+            debug!("access point at {} bytes", writer.byte_count());
+            points.push(AccessPoint {
+                bit_offset: header_len * 8 + deflate_reader.bit_position(),
+                offset: writer.byte_count(),
+                window: writer.window(),
+            });
+        }
+    }
+    MemberFooter::read(&mut input)?.verify(writer.byte_count(), Some(writer.current_crc32()))?;
+    Ok(GzipIndex {
+        points,
+        len: writer.byte_count(),
+    })
+}
+
+/// Decompress `len` bytes of data starting at `offset`, or fewer at the end of the
+/// data, from `input` indexed by `index`. Decoding starts at the last access point
+/// before `offset`, and nothing is checked against the footer.
+pub fn read_at<R: BufRead + Seek>(
+    index: &GzipIndex,
+    mut input: R,
+    offset: u64,
+    len: usize,
+) -> Result<Vec<u8>> {
+    let point = &index.points[index.points.partition_point(|point| point.offset <= offset) - 1];
+    debug!("decoding from the access point at {} bytes", point.offset);
+    input.seek(SeekFrom::Start(point.bit_offset / 8))?;
+    let mut bit_reader = BitReader::new(input);
+    let bits_to_skip = (point.bit_offset % 8) as u8;
+    if bits_to_skip != 0 {
+        bit_reader.read_bits(bits_to_skip)?;
+    }
+
+    let mut deflate_reader = DeflateReader::with_dictionary(bit_reader, &point.window);
+    let mut writer = TrackingWriter::new_without_crc(RangeWriter {
+        skip: offset - point.offset,
+        data: Vec::with_capacity(len),
+        len,
+    });
+    while writer.get_ref().data.len() < len
+        && deflate_reader.decode_some(&mut writer, CHUNK_SIZE)?
+    {}
+    Ok(std::mem::take(&mut writer.get_mut().data))
+}
+
+/// Writer keeping `len` bytes after the first `skip` ones, and discarding the rest.
+struct RangeWriter {
+    skip: u64,
+    data: Vec<u8>,
+    len: usize,
+}
+
+impl Write for RangeWriter {
+    fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
+        let written_len = buf.len();
+        let skip_len = buf
+            .len()
+            .min(usize::try_from(self.skip).unwrap_or(usize::MAX));
+        self.skip -= skip_len as u64;
+        buf = &buf[skip_len..];
+        let keep_len = buf.len().min(self.len - self.data.len());
+        self.data.extend_from_slice(&buf[..keep_len]);
+        Ok(written_len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
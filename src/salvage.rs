@@ -0,0 +1,81 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, BufRead, Read, Write};
+
+use log::*;
+
+use crate::error::{DecodeError, Result};
+use crate::gzip::{self, GzipReader, MemberInfo};
+use crate::tracking_writer::TrackingWriter;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// What became of the member found at `offset` bytes into the input.
+#[derive(Debug)]
+pub struct MemberOutcome {
+    pub offset: u64,
+    pub result: Result<MemberInfo>,
+}
+
+/// Same as `decompress`, but a member which fails to decode is recorded and skipped:
+/// decoding resumes at the next ID1, ID2 and CM of deflate in the input. The data a
+/// failed member decoded before the error is kept in `output`. Only I/O errors stop
+/// decoding, other errors are returned in the outcome of their member.
+pub fn decompress_lenient<R: BufRead, W: Write>(input: R, output: W) -> Result<Vec<MemberOutcome>> {
+    let mut input = CountingReader {
+        inner: input,
+        count: 0,
+    };
+    let mut writer = TrackingWriter::new(output);
+    let mut outcomes = vec![];
+    /* The magic consumed when looking for the next member, replayed to parse it. */
+    let mut prefix: &[u8] = &[];
+    while !prefix.is_empty() || !input.fill_buf()?.is_empty() {
+        let offset = input.count - prefix.len() as u64;
+        let result = GzipReader::new((&mut prefix).chain(&mut input))
+            .decompress_member_into(&mut writer, true)
+            .map(|(info, _)| info);
+        if let Err(DecodeError::Io(err)) = result {
+            return Err(err.into());
+        }
+        let failed = result.is_err();
+        outcomes.push(MemberOutcome { offset, result });
+        if failed {
+            warn!("skipping the corrupt member at offset {}", offset);
+            match gzip::skip_to_magic(&mut input, u64::MAX) {
+                Ok(skipped) => {
+                    info!("resuming after {} bytes", skipped);
+                    prefix = &gzip::MAGIC;
+                }
+                Err(DecodeError::BadMagic) => break,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+    Ok(outcomes)
+}
+
+/// Keeps the number of bytes consumed from the inner reader.
+struct CountingReader<T> {
+    inner: T,
+    count: u64,
+}
+
+impl<T: BufRead> Read for CountingReader<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+}
+
+impl<T: BufRead> BufRead for CountingReader<T> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.count += amt as u64;
+    }
+}
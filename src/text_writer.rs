@@ -0,0 +1,88 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, Write};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Writer that turns CRLF line endings into LF while enabled, and passes the data
+/// through unchanged otherwise.
+pub struct TextWriter<T> {
+    inner: T,
+    enabled: bool,
+    /// A CR ended the previous write, it is dropped if the next byte is LF.
+    pending_cr: bool,
+}
+
+impl<T: Write> Write for TextWriter<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.enabled {
+            return self.inner.write(buf);
+        }
+        let mut normalized = Vec::with_capacity(buf.len() + 1);
+        for &byte in buf {
+            if self.pending_cr && byte != b'\n' {
+                normalized.push(b'\r');
+            }
+            self.pending_cr = byte == b'\r';
+            if !self.pending_cr {
+                normalized.push(byte);
+            }
+        }
+        self.inner.write_all(&normalized)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Write> TextWriter<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            enabled: false,
+            pending_cr: false,
+        }
+    }
+
+    /// Switch the normalization on or off, e.g. from member to member.
+    pub fn set_enabled(&mut self, enabled: bool) -> io::Result<()> {
+        if !enabled {
+            self.finish()?;
+        }
+        self.enabled = enabled;
+        Ok(())
+    }
+
+    /// Write out a CR left pending at the end of the data.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.pending_cr {
+            self.pending_cr = false;
+            self.inner.write_all(b"\r")?;
+        }
+        Ok(())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write() -> io::Result<()> {
+        let mut writer = TextWriter::new(vec![]);
+        writer.write_all(b"a\r\nb")?;
+        writer.set_enabled(true)?;
+        writer.write_all(b"c\r\nd\r")?;
+        writer.write_all(b"\ne\r\r\nf\r")?;
+        writer.finish()?;
+        writer.write_all(b"g\r")?;
+        writer.set_enabled(false)?;
+        writer.write_all(b"\r\n")?;
+        assert_eq!(writer.inner, b"a\r\nbc\nd\ne\r\nf\rg\r\r\n");
+        Ok(())
+    }
+}
@@ -1,36 +1,49 @@
 #![forbid(unsafe_code)]
 
-use std::collections::VecDeque;
-use std::io::{self, Write};
+use alloc::{boxed::Box, vec, vec::Vec};
+
+use crate::io::{self, Write};
 
-use anyhow::{anyhow, ensure, Result};
 use crc::{Crc, Digest};
 
+use crate::adler32::Adler32;
+use crate::error::{ensure, DecodeError, Result};
+
 ////////////////////////////////////////////////////////////////////////////////
 
 const HISTORY_SIZE: usize = 32768;
 
+static CRC: Crc<u32> = Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+#[derive(Clone)]
 pub struct TrackingWriter<T> {
     inner: T,
-    history: VecDeque<u8>,
-    byte_count: usize,
+    /// Ring buffer holding the last `history_len` written bytes, ending right before
+    /// `history_pos`.
+    history: Box<[u8]>,
+    history_pos: usize,
+    history_len: usize,
+    /// Reused by `write_previous` to assemble the referenced bytes.
+    scratch: Vec<u8>,
+    byte_count: u64,
     digest: Digest<'static, u32>,
+    /// Unset to skip computing the CRC-32 of trusted data.
+    computes_crc32: bool,
+    adler32: Option<Adler32>,
 }
 
 impl<T: Write> Write for TrackingWriter<T> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let written_len = self.inner.write(buf)?;
         let written = &buf[..written_len];
-        self.digest.update(written);
-
-        if written_len > HISTORY_SIZE {
-            self.history.clear();
-        } else if written_len + self.history.len() > HISTORY_SIZE {
-            self.history
-                .drain(..(written_len + self.history.len() - HISTORY_SIZE));
+        if self.computes_crc32 {
+            self.digest.update(written);
         }
-        self.history.extend(written);
-        self.byte_count += written_len;
+        if let Some(adler32) = &mut self.adler32 {
+            adler32.update(written);
+        }
+        self.push_history(written);
+        self.byte_count += written_len as u64;
         Ok(written_len)
     }
 
@@ -41,52 +54,189 @@ impl<T: Write> Write for TrackingWriter<T> {
 
 impl<T: Write> TrackingWriter<T> {
     pub fn new(inner: T) -> Self {
-        static CRC: Crc<u32> = Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+        Self::with_window_size(inner, HISTORY_SIZE)
+    }
+
+    /// Same as `new`, but only keeps the last `window_size` bytes for back-references,
+    /// which has to be a power of two from 256 to 32768.
+    pub fn with_window_size(inner: T, window_size: usize) -> Self {
+        assert!(
+            window_size.is_power_of_two() && (256..=HISTORY_SIZE).contains(&window_size),
+            "invalid window size {}",
+            window_size
+        );
         Self {
             inner,
-            history: VecDeque::<u8>::with_capacity(HISTORY_SIZE),
+            history: vec![0; window_size].into_boxed_slice(),
+            history_pos: 0,
+            history_len: 0,
+            scratch: Vec::new(),
             byte_count: 0,
             digest: CRC.digest(),
+            computes_crc32: true,
+            adler32: None,
+        }
+    }
+
+    /// Same as `new`, but also computes the Adler-32 checksum of the written data.
+    pub fn new_with_adler32(inner: T) -> Self {
+        Self {
+            adler32: Some(Adler32::new()),
+            ..Self::new(inner)
+        }
+    }
+
+    /// Same as `new`, but doesn't compute the CRC-32, which stays that of no data.
+    pub fn new_without_crc(inner: T) -> Self {
+        Self {
+            computes_crc32: false,
+            ..Self::new(inner)
         }
     }
 
     /// Write a sequence of `len` bytes written `dist` bytes ago.
     pub fn write_previous(&mut self, dist: usize, len: usize) -> Result<()> {
-        ensure!(dist < self.history.len(), "Trying to write very far");
-
-        let past_begin = self.history.len() - dist;
-        let past_end = if dist <= len {
-            self.history.len()
-        } else {
-            self.history.len() - dist + len
+        let mut chunk = core::mem::take(&mut self.scratch);
+        chunk.clear();
+        let result = self.resolve_match(dist, len, &mut chunk);
+        let result = match result {
+            Ok(()) => self.write_all(&chunk).map_err(DecodeError::from),
+            err => err,
         };
+        self.scratch = chunk;
+        result
+    }
+
+    /// Append to `out` the sequence of `len` bytes written `dist` bytes ago, without
+    /// writing it to the inner writer nor adding it to the history.
+    #[inline]
+    pub fn resolve_match(&self, dist: usize, len: usize, out: &mut Vec<u8>) -> Result<()> {
+        let size = self.history.len();
+        ensure!(dist != 0, DecodeError::InvalidDistance);
+        ensure!(
+            dist <= size,
+            DecodeError::DistanceTooFar {
+                distance: dist,
+                window_size: size,
+            }
+        );
+        ensure!(
+            dist <= self.history_len,
+            DecodeError::DistanceBeforeStart {
+                distance: dist,
+                available: self.history_len,
+            }
+        );
 
-        let mut chunk: Vec<u8> = self.history.range(past_begin..past_end).copied().collect();
+        let past_begin = (self.history_pos + size - dist) % size;
+        let past_len = dist.min(len);
 
-        let initial_len = chunk.len();
-        while chunk.len() < len {
-            chunk.extend_from_within(0..initial_len);
-            if chunk.len() > len {
-                chunk.truncate(len);
+        let start = out.len();
+        if dist == 1 {
+            /* A run of the last byte, the most common overlapping reference. */
+            out.resize(start + len, self.history[past_begin]);
+        } else {
+            let first_len = past_len.min(size - past_begin);
+            out.extend_from_slice(&self.history[past_begin..past_begin + first_len]);
+            out.extend_from_slice(&self.history[..past_len - first_len]);
+
+            /*
+             * Overlapping reference: the copied bytes repeat with period `dist`.
+             * Doubling the copy tiles any distance with a logarithmic number of copies.
+             */
+            while out.len() - start < len {
+                let repeat_len = (out.len() - start).min(start + len - out.len());
+                out.extend_from_within(start..start + repeat_len);
             }
         }
+        Ok(())
+    }
 
-        match self.write(&chunk) {
-            Ok(written) => {
-                if written == len {
-                    Ok(())
-                } else {
-                    Err(anyhow!("written less"))
-                }
-            }
-            Err(msg) => Err(anyhow!(msg)),
+    fn push_history(&mut self, mut data: &[u8]) {
+        let size = self.history.len();
+        if data.len() > size {
+            data = &data[data.len() - size..];
         }
+        let first_len = data.len().min(size - self.history_pos);
+        self.history[self.history_pos..self.history_pos + first_len]
+            .copy_from_slice(&data[..first_len]);
+        self.history[..data.len() - first_len].copy_from_slice(&data[first_len..]);
+        self.history_pos = (self.history_pos + data.len()) % size;
+        self.history_len = (self.history_len + data.len()).min(size);
+    }
+
+    /// Seeds the history window with `dictionary` so that back-references can
+    /// point into it. The dictionary itself is neither written nor checksummed.
+    pub fn preload_history(&mut self, dictionary: &[u8]) {
+        self.push_history(dictionary);
     }
 
-    pub fn byte_count(&self) -> usize {
+    /// Enlarge the history window to `size` bytes, keeping its contents. A smaller
+    /// `size` is ignored.
+    pub fn grow_history(&mut self, size: usize) {
+        if size <= self.history.len() {
+            return;
+        }
+        let mut history = self.window();
+        self.history_pos = history.len();
+        history.resize(size, 0);
+        self.history = history.into_boxed_slice();
+    }
+
+    /// The bytes in the history window, oldest first, e.g. to resume decoding later
+    /// with them as a preset dictionary.
+    pub fn window(&self) -> Vec<u8> {
+        let size = self.history.len();
+        let begin = (self.history_pos + size - self.history_len) % size;
+        let first_len = self.history_len.min(size - begin);
+        let mut window = Vec::with_capacity(self.history_len);
+        window.extend_from_slice(&self.history[begin..begin + first_len]);
+        window.extend_from_slice(&self.history[..self.history_len - first_len]);
+        window
+    }
+
+    /// Forget the written data, as if the writer was new, but keep the allocated
+    /// buffers and the inner writer as is.
+    pub fn reset(&mut self) {
+        self.history_pos = 0;
+        self.history_len = 0;
+        self.byte_count = 0;
+        self.digest = CRC.digest();
+        if self.adler32.is_some() {
+            self.adler32 = Some(Adler32::new());
+        }
+    }
+
+    /// Reference to the inner writer, e.g. to check its own state while the writer
+    /// stays usable. Only `crc32` gives the inner writer back for good.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Mutable reference to the inner writer. Data written to it directly bypasses
+    /// the history and the checksums.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Number of bytes written, not limited to the 32 bits of gzip's ISIZE.
+    pub fn byte_count(&self) -> u64 {
         self.byte_count
     }
 
+    pub fn adler32(&self) -> Option<u32> {
+        self.adler32.map(Adler32::finalize)
+    }
+
+    pub fn computes_crc32(&self) -> bool {
+        self.computes_crc32
+    }
+
+    /// CRC-32 of the data written so far. The writer stays usable.
+    pub fn current_crc32(&self) -> u32 {
+        self.digest.clone().finalize()
+    }
+
     pub fn crc32(self) -> (u32, T) {
         (self.digest.finalize(), self.inner)
     }
@@ -122,9 +272,88 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn adler32() -> Result<()> {
+        let mut writer = TrackingWriter::new(vec![]);
+        writer.write_all(b"Wiki")?;
+        assert_eq!(writer.adler32(), None);
+
+        let mut writer = TrackingWriter::new_with_adler32(vec![]);
+        writer.write_all(b"Wikipedia")?;
+        assert_eq!(writer.adler32(), Some(0x11e60398));
+        writer.write_all(b" ")?;
+        writer.write_previous(10, 9)?;
+        assert_eq!(writer.adler32(), Some(0x48f3074f));
+
+        let (crc, output) = writer.crc32();
+        assert_eq!(output, b"Wikipedia Wikipedia");
+        assert_eq!(crc, 0x13d6c6e7);
+
+        Ok(())
+    }
+
+    #[test]
+    fn current_crc32() -> Result<()> {
+        let mut writer = TrackingWriter::new(vec![]);
+        assert_eq!(writer.current_crc32(), 0);
+        writer.write_all(b"Wikipedia")?;
+        assert_eq!(writer.current_crc32(), 0xadaac02e);
+        writer.write_all(b" ")?;
+        writer.write_previous(10, 9)?;
+        assert_eq!(writer.current_crc32(), 0x13d6c6e7);
+        assert_eq!(writer.crc32().0, 0x13d6c6e7);
+        Ok(())
+    }
+
+    #[test]
+    fn get_ref() -> Result<()> {
+        let mut writer = TrackingWriter::new(vec![]);
+        writer.write_all(b"abc")?;
+        assert_eq!(writer.get_ref(), b"abc");
+        assert_eq!(CRC.checksum(writer.get_ref()), writer.current_crc32());
+        writer.get_mut().clear();
+        writer.write_all(b"d")?;
+        assert_eq!(writer.get_ref(), b"d");
+        assert_eq!(writer.byte_count(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn window() -> Result<()> {
+        let mut writer = TrackingWriter::with_window_size(vec![], 256);
+        assert!(writer.window().is_empty());
+        writer.write_all(b"abc")?;
+        assert_eq!(writer.window(), b"abc");
+        let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        writer.write_all(&data)?;
+        assert_eq!(writer.window(), &data[1000 - 256..]);
+        Ok(())
+    }
+
+    #[test]
+    fn preload_history() -> Result<()> {
+        let mut writer = TrackingWriter::new(vec![]);
+        writer.preload_history(b"abc");
+        writer.write_previous(3, 5)?;
+        writer.write_all(b"d")?;
+        writer.write_previous(7, 2)?;
+        assert_eq!(writer.byte_count(), 8);
+
+        let (crc, output) = writer.crc32();
+        assert_eq!(output, b"abcabdca");
+        assert_eq!(crc, 0xe5ec3457);
+
+        let mut writer = TrackingWriter::new(vec![]);
+        writer.preload_history(&[0; HISTORY_SIZE + 1]);
+        writer.write_previous(HISTORY_SIZE, 1)?;
+        assert!(writer.write_previous(HISTORY_SIZE + 1, 1).is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn write_previous() -> Result<()> {
-        let mut buf: &mut [u8] = &mut [0u8; 512];
+        let mut buf: &mut [u8] = &mut [0u8; 640];
         let mut writer = TrackingWriter::new(&mut buf);
 
         for i in 0..=255 {
@@ -137,15 +366,187 @@ mod tests {
         assert!(writer.write_previous(10000, 20).is_err());
         assert_eq!(writer.byte_count(), 384);
 
-        assert!(writer.write_previous(256, 256).is_err());
-        assert_eq!(writer.byte_count(), 512);
+        writer.write_previous(256, 256)?;
+        assert_eq!(writer.byte_count(), 640);
 
+        /* The inner slice is full. */
         assert!(writer.write_previous(1, 1).is_err());
-        assert_eq!(writer.byte_count(), 512);
+        assert_eq!(writer.byte_count(), 640);
 
         let (crc, _) = writer.crc32();
-        assert_eq!(crc, 2733545866);
+        assert_eq!(crc, 2460609489);
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_previous_wrapped_history() -> Result<()> {
+        let mut writer = TrackingWriter::new(vec![]);
 
+        let data: Vec<u8> = (0..HISTORY_SIZE + 1000).map(|i| (i % 251) as u8).collect();
+        writer.write_all(&data)?;
+        writer.write_previous(HISTORY_SIZE, 1500)?;
+        assert!(writer.write_previous(HISTORY_SIZE + 1, 1).is_err());
+
+        let (_, output) = writer.crc32();
+        assert_eq!(output[data.len()..], data[1000..2500]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn with_window_size() -> Result<()> {
+        let mut writer = TrackingWriter::with_window_size(vec![], 256);
+        writer.write_all(&[1; 200])?;
+        writer.write_all(&[2; 100])?;
+        writer.write_previous(256, 10)?;
+        assert!(writer.write_previous(257, 1).is_err());
+        assert_eq!(writer.get_mut()[300..], [1; 10]);
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic]
+    fn with_window_size_not_power_of_two() {
+        TrackingWriter::with_window_size(vec![], 1000);
+    }
+
+    #[test]
+    fn reset() -> Result<()> {
+        let mut writer = TrackingWriter::new_with_adler32(vec![]);
+        writer.write_all(b"some data")?;
+        writer.reset();
+        assert_eq!(writer.byte_count(), 0);
+        assert!(writer.write_previous(1, 1).is_err());
+
+        writer.write_all(b"Wikipedia")?;
+        let mut fresh = TrackingWriter::new_with_adler32(vec![]);
+        fresh.write_all(b"Wikipedia")?;
+        assert_eq!(writer.byte_count(), fresh.byte_count());
+        assert_eq!(writer.adler32(), fresh.adler32());
+        assert_eq!(writer.current_crc32(), fresh.current_crc32());
+        assert_eq!(writer.get_mut(), b"some dataWikipedia");
+        Ok(())
+    }
+
+    #[test]
+    fn grow_history() -> Result<()> {
+        let mut writer = TrackingWriter::new(vec![]);
+
+        let data: Vec<u8> = (0..HISTORY_SIZE + 1000).map(|i| (i % 251) as u8).collect();
+        writer.write_all(&data)?;
+        writer.grow_history(2 * HISTORY_SIZE);
+        writer.write_previous(HISTORY_SIZE, 10)?;
+        assert!(writer.write_previous(HISTORY_SIZE + 11, 1).is_err());
+
+        writer.write_all(&data)?;
+        writer.write_previous(2 * HISTORY_SIZE, 10)?;
+        assert!(writer.write_previous(2 * HISTORY_SIZE + 1, 1).is_err());
+
+        let (_, output) = writer.crc32();
+        let len = output.len();
+        assert_eq!(
+            output[len - 10..],
+            output[len - 10 - 2 * HISTORY_SIZE..][..10]
+        );
+        assert_eq!(output[data.len()..][..10], data[1000..1010]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_previous_run() -> Result<()> {
+        let mut writer = TrackingWriter::new(vec![]);
+        writer.write_all(b"xyz")?;
+        writer.write_previous(1, 1000)?;
+        writer.write_previous(4, 6)?;
+        assert_eq!(writer.byte_count(), 1009);
+
+        let mut expected = b"xy".to_vec();
+        expected.extend([b'z'; 1001]);
+        expected.extend(b"zzzzzz");
+        let (crc, output) = writer.crc32();
+        assert_eq!(output, expected);
+        assert_eq!(crc, CRC.checksum(&expected));
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_match() -> Result<()> {
+        let mut writer = TrackingWriter::new(vec![]);
+        writer.write_all(b"abcd")?;
+
+        let mut out = b"head:".to_vec();
+        writer.resolve_match(3, 8, &mut out)?;
+        writer.resolve_match(1, 3, &mut out)?;
+        assert_eq!(out, b"head:bcdbcdbcddd");
+        assert!(matches!(
+            writer.resolve_match(5, 1, &mut out),
+            Err(DecodeError::DistanceBeforeStart { .. })
+        ));
+
+        assert_eq!(writer.byte_count(), 4);
+        assert_eq!(writer.get_mut(), b"abcd");
+        Ok(())
+    }
+
+    #[test]
+    fn write_previous_errors() -> Result<()> {
+        let mut writer = TrackingWriter::with_window_size(vec![], 256);
+        writer.write_all(b"abc")?;
+        assert!(matches!(
+            writer.write_previous(0, 1),
+            Err(DecodeError::InvalidDistance)
+        ));
+        assert!(matches!(
+            writer.write_previous(4, 1),
+            Err(DecodeError::DistanceBeforeStart {
+                distance: 4,
+                available: 3
+            })
+        ));
+        writer.write_all(&[0; 300])?;
+        assert!(matches!(
+            writer.write_previous(257, 1),
+            Err(DecodeError::DistanceTooFar {
+                distance: 257,
+                window_size: 256
+            })
+        ));
+        writer.write_previous(256, 1)?;
+        assert_eq!(writer.byte_count(), 304);
+        Ok(())
+    }
+
+    #[test]
+    fn write_previous_whole_history() -> Result<()> {
+        let mut writer = TrackingWriter::new(vec![]);
+
+        assert!(writer.write_previous(1, 1).is_err());
+
+        writer.write_all(b"abc")?;
+        writer.write_previous(3, 5)?;
+        assert!(writer.write_previous(0, 1).is_err());
+        assert!(writer.write_previous(9, 1).is_err());
+        writer.write_previous(8, 2)?;
+        assert_eq!(writer.byte_count(), 10);
+
+        let (_, output) = writer.crc32();
+        assert_eq!(output, b"abcabcabab");
+
+        Ok(())
+    }
+
+    #[test]
+    fn byte_count_past_4gib() -> Result<()> {
+        let chunk = vec![7; 1 << 20];
+        let mut writer = TrackingWriter::new_without_crc(std::io::sink());
+        for _ in 0..4097 {
+            writer.write_all(&chunk)?;
+        }
+        assert_eq!(writer.byte_count(), (1 << 32) + (1 << 20));
+        assert!(!writer.computes_crc32());
+        assert_eq!(writer.current_crc32(), 0);
         Ok(())
     }
 }
@@ -0,0 +1,111 @@
+#![forbid(unsafe_code)]
+
+use std::io::{BufRead, Write};
+
+use byteorder::{BigEndian, ReadBytesExt};
+use log::*;
+
+use crate::{
+    adler32::Adler32,
+    bit_reader::BitReader,
+    deflate::DeflateReader,
+    error::{ensure, DecodeError, EofContext, Result},
+    tracking_writer::TrackingWriter,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+const CM_DEFLATE: u8 = 8;
+const MAX_CINFO: u8 = 7;
+
+const FDICT_OFFSET: u8 = 5;
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct ZlibReader<T> {
+    reader: T,
+    dictionary: Vec<u8>,
+}
+
+impl<T: BufRead> ZlibReader<T> {
+    pub fn new(reader: T) -> Self {
+        Self {
+            reader,
+            dictionary: Vec::new(),
+        }
+    }
+
+    /// Same as `new`, but streams with FDICT set are decoded using `dictionary`
+    /// as long as its Adler-32 matches the one in the header.
+    pub fn with_dictionary(reader: T, dictionary: &[u8]) -> Self {
+        Self {
+            reader,
+            dictionary: dictionary.to_vec(),
+        }
+    }
+
+    pub fn decompress<W: Write>(mut self, output: W) -> Result<(T, W)> {
+        info!("parsing zlib header");
+        let dict_id = self
+            .parse_header()
+            .map_err(|err| err.in_context(EofContext::Header))?;
+
+        info!("parsing deflate format");
+        let mut writer = TrackingWriter::new_with_adler32(output);
+        let bit_reader = BitReader::new(&mut self.reader);
+        let mut deflate_reader = match dict_id {
+            Some(dict_id) => {
+                let mut adler32 = Adler32::new();
+                adler32.update(&self.dictionary);
+                ensure!(
+                    adler32.finalize() == dict_id,
+                    DecodeError::MissingDictionary(dict_id)
+                );
+                DeflateReader::with_dictionary(bit_reader, &self.dictionary)
+            }
+            None => DeflateReader::new(bit_reader),
+        };
+        deflate_reader.deflate_tracked(&mut writer)?;
+
+        let data_adler32 = self
+            .reader
+            .read_u32::<BigEndian>()
+            .map_err(|err| DecodeError::from(err).in_context(EofContext::Footer))?;
+        ensure!(
+            Some(data_adler32) == writer.adler32(),
+            DecodeError::Adler32Mismatch
+        );
+        let (_, output) = writer.crc32();
+        Ok((self.reader, output))
+    }
+
+    /// Returns the id of the preset dictionary, if the stream requires one.
+    fn parse_header(&mut self) -> Result<Option<u32>> {
+        let cmf = self.reader.read_u8()?;
+        let flg = self.reader.read_u8()?;
+        debug!("CMF:\t{:#010b}", cmf);
+        debug!("FLG:\t{:#010b}", flg);
+        ensure!(
+            (u16::from(cmf) << 8 | u16::from(flg)) % 31 == 0,
+            DecodeError::InvalidHeader("zlib header check failed")
+        );
+
+        let method = cmf & 0x0f;
+        ensure!(
+            method == CM_DEFLATE,
+            DecodeError::UnsupportedCompressionMethod(method)
+        );
+        ensure!(
+            cmf >> 4 <= MAX_CINFO,
+            DecodeError::InvalidHeader("zlib window size is too large")
+        );
+
+        if (flg >> FDICT_OFFSET) & 1 != 0 {
+            let dict_id = self.reader.read_u32::<BigEndian>()?;
+            debug!("DICTID:\t{:#x}", dict_id);
+            return Ok(Some(dict_id));
+        }
+
+        Ok(None)
+    }
+}
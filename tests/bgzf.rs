@@ -0,0 +1,67 @@
+use std::io::{Cursor, Read};
+
+use ripgzip::{BgzfReader, DecodeError};
+
+/// Four BGZF blocks of up to 65280 bytes, as written by bgzip, and the end of file
+/// marker of the BGZF specification.
+const DATA: &[u8] = include_bytes!("../data/ok/12-bgzf.gz");
+const BLOCK_OFFSETS: [u64; 5] = [0, 28685, 56771, 85069, 87252];
+
+#[test]
+fn read() {
+    let expected = ripgzip::decompress_slice(DATA).unwrap();
+    assert_eq!(expected.len(), 200000);
+
+    let mut reader = BgzfReader::new(DATA);
+    let mut output = vec![];
+    reader.read_to_end(&mut output).unwrap();
+    assert_eq!(output, expected);
+    assert_eq!(reader.virtual_offset(), BLOCK_OFFSETS[4] << 16);
+
+    let mut reader = BgzfReader::new(DATA);
+    reader.read_exact(&mut [0; 65280]).unwrap();
+    assert_eq!(reader.virtual_offset(), 65280);
+    reader.read_exact(&mut [0; 10]).unwrap();
+    assert_eq!(reader.virtual_offset(), BLOCK_OFFSETS[1] << 16 | 10);
+}
+
+#[test]
+fn seek_to_block() {
+    let expected = ripgzip::decompress_slice(DATA).unwrap();
+
+    let mut reader = BgzfReader::new(Cursor::new(DATA));
+    for (idx, &block_offset) in BLOCK_OFFSETS[..4].iter().enumerate().rev() {
+        let virtual_offset = block_offset << 16 | 1234;
+        reader.seek_to_block(virtual_offset).unwrap();
+        assert_eq!(reader.virtual_offset(), virtual_offset);
+        let mut output = vec![];
+        reader.read_to_end(&mut output).unwrap();
+        assert_eq!(output, expected[idx * 65280 + 1234..]);
+    }
+
+    reader.seek_to_block(BLOCK_OFFSETS[4] << 16).unwrap();
+    assert_eq!(reader.read(&mut [0; 16]).unwrap(), 0);
+    assert!(reader
+        .seek_to_block(BLOCK_OFFSETS[3] << 16 | 10000)
+        .is_err());
+}
+
+#[test]
+fn not_bgzf() {
+    let data: &[u8] = include_bytes!("../data/ok/00-Cargo.toml.gz");
+    let err = BgzfReader::new(data)
+        .read_to_end(&mut vec![])
+        .unwrap_err()
+        .into_inner()
+        .unwrap();
+    assert_eq!(err.to_string(), "invalid header: missing BGZF block size");
+
+    /* BSIZE of the end of file marker, one byte too large. */
+    let mut data = DATA.to_vec();
+    let len = data.len();
+    data[len - 12] += 1;
+    assert!(matches!(
+        BgzfReader::new(Cursor::new(data)).seek_to_block(BLOCK_OFFSETS[4] << 16),
+        Err(DecodeError::InvalidHeader(_))
+    ));
+}
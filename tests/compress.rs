@@ -0,0 +1,107 @@
+use ripgzip::{CompressionLevel, GzipWriter, MemberHeader};
+
+const LEVELS: [CompressionLevel; 2] = [CompressionLevel::Store, CompressionLevel::Fixed];
+
+#[test]
+fn compress_round_trip() {
+    let samples: [&[u8]; 3] = [
+        include_bytes!("../data/ok/00-Cargo.toml.gz"),
+        include_bytes!("../data/ok/03-photo.jpg.gz"),
+        include_bytes!("../data/ok/09-concat.gz"),
+    ];
+    for sample in samples {
+        let data = ripgzip::decompress_to_vec(sample).unwrap();
+        for level in LEVELS {
+            let mut compressed = vec![];
+            ripgzip::compress(data.as_slice(), &mut compressed, level).unwrap();
+            assert_eq!(
+                ripgzip::decompress_to_vec(compressed.as_slice()).unwrap(),
+                data
+            );
+            assert_eq!(ripgzip::verify(compressed.as_slice()).unwrap().len(), 1);
+        }
+    }
+
+    for level in LEVELS {
+        let mut compressed = vec![];
+        ripgzip::compress(&b""[..], &mut compressed, level).unwrap();
+        assert!(ripgzip::decompress_to_vec(compressed.as_slice())
+            .unwrap()
+            .is_empty());
+    }
+}
+
+#[test]
+fn compress_fixed_matches() {
+    let data = include_bytes!("../Cargo.lock").repeat(4);
+    let mut compressed = vec![];
+    ripgzip::compress(data.as_slice(), &mut compressed, CompressionLevel::Fixed).unwrap();
+    assert!(compressed.len() < data.len() / 4);
+    assert_eq!(
+        ripgzip::decompress_to_vec(compressed.as_slice()).unwrap(),
+        data
+    );
+}
+
+#[test]
+fn deflate_raw() {
+    let data = include_bytes!("../Cargo.lock");
+    for level in LEVELS {
+        let mut compressed = vec![];
+        ripgzip::deflate_raw(data, &mut compressed, level).unwrap();
+        let mut output = vec![];
+        ripgzip::inflate_raw(compressed.as_slice(), &mut output).unwrap();
+        assert_eq!(output, data);
+    }
+}
+
+#[test]
+fn gzip_writer() {
+    let header = MemberHeader {
+        modification_time: 1617120213,
+        name: Some("hello.txt".to_owned()),
+        comment: Some("greeting".to_owned()),
+        has_crc: true,
+        ..Default::default()
+    };
+    let (footer, compressed) = GzipWriter::new(vec![], header)
+        .compress(&b"Hello, world!\n"[..], CompressionLevel::Fixed)
+        .unwrap();
+    assert_eq!(footer.data_size, 14);
+
+    let mut output = vec![];
+    let headers = ripgzip::decompress_with_headers(compressed.as_slice(), &mut output).unwrap();
+    assert_eq!(output, b"Hello, world!\n");
+    assert_eq!(headers.len(), 1);
+    assert_eq!(headers[0].name.as_deref(), Some("hello.txt"));
+    assert_eq!(headers[0].comment.as_deref(), Some("greeting"));
+    assert_eq!(headers[0].modification_time, 1617120213);
+    assert!(headers[0].has_crc);
+    assert_eq!(ripgzip::verify(compressed.as_slice()).unwrap(), [footer]);
+}
+
+#[test]
+fn store() {
+    const HEADER_LEN: usize = 10;
+    const FOOTER_LEN: usize = 8;
+    const BLOCK_HEADER_LEN: usize = 5;
+
+    for (len, blocks) in [(0, 1), (1, 1), (65535, 1), (65536, 2), (2 * 65535, 2)] {
+        let data: Vec<u8> = (0..len).map(|i| (i % 253) as u8).collect();
+        let mut compressed = vec![];
+        ripgzip::store(&data, &mut compressed).unwrap();
+        assert_eq!(
+            compressed.len(),
+            HEADER_LEN + blocks * BLOCK_HEADER_LEN + len + FOOTER_LEN
+        );
+
+        let body = &compressed[HEADER_LEN..compressed.len() - FOOTER_LEN];
+        let mut output = vec![];
+        let stats = ripgzip::inflate_raw_with_stats(body, &mut output).unwrap();
+        assert_eq!(stats.stored_blocks as usize, blocks);
+        assert_eq!(output, data);
+
+        let footers = ripgzip::verify(compressed.as_slice()).unwrap();
+        assert_eq!(footers[0].data_size as usize, len);
+    }
+}
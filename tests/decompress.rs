@@ -0,0 +1,988 @@
+#[test]
+fn decompress_to_vec() {
+    let data: &[u8] = include_bytes!("../data/ok/00-Cargo.toml.gz");
+    let output = ripgzip::decompress_to_vec(data).unwrap();
+    assert_eq!(output.len(), 295);
+    assert_eq!(output.capacity(), 295);
+    assert!(output.starts_with(b"[package]\n"));
+
+    /* Without the footer in the buffer, the hint is only a guess. */
+    let data: &[u8] = include_bytes!("../data/ok/06-war-and-peace.txt.gz");
+    let reader = std::io::BufReader::with_capacity(1024, data);
+    assert_eq!(ripgzip::decompress_to_vec(reader).unwrap().len(), 1543130);
+
+    let data: &[u8] = include_bytes!("../data/ok/11-named-concat.gz");
+    let output = ripgzip::decompress_to_vec(data).unwrap();
+    assert_eq!(output, b"Hello, world!\nSecond member.\n");
+
+    let data: &[u8] = include_bytes!("../data/corrupted/01-bad-crc32.gz");
+    assert!(ripgzip::decompress_to_vec(data).is_err());
+}
+
+#[test]
+fn decompress_slice() {
+    let data: &[u8] = include_bytes!("../data/ok/00-Cargo.toml.gz");
+    let output = ripgzip::decompress_slice(data).unwrap();
+    assert_eq!(output.capacity(), 295);
+    assert_eq!(output, ripgzip::decompress_to_vec(data).unwrap());
+
+    let data: &[u8] = include_bytes!("../data/ok/11-named-concat.gz");
+    let output = ripgzip::decompress_slice(data).unwrap();
+    assert_eq!(output, b"Hello, world!\nSecond member.\n");
+
+    let data: &[u8] = include_bytes!("../data/corrupted/01-bad-crc32.gz");
+    assert!(ripgzip::decompress_slice(data).is_err());
+}
+
+#[test]
+fn decompress_limited() {
+    let mut data: &[u8] = include_bytes!("../data/ok/09-concat.gz");
+    let mut output = vec![];
+    ripgzip::decompress_limited(&mut data, &mut output, 1784657).unwrap();
+    assert_eq!(output.len(), 1784657);
+
+    let mut data: &[u8] = include_bytes!("../data/ok/09-concat.gz");
+    let mut output = vec![];
+    let err = ripgzip::decompress_limited(&mut data, &mut output, 100000).unwrap_err();
+    assert!(matches!(
+        err,
+        ripgzip::DecodeError::OutputLimitExceeded {
+            limit: 100000,
+            written: 99984
+        }
+    ));
+    assert_eq!(
+        err.to_string(),
+        "output limit of 100000 bytes exceeded after 99984 bytes"
+    );
+    assert_eq!(output.len(), 99984);
+}
+
+#[test]
+fn inflate_raw() {
+    let gzip: &[u8] = include_bytes!("../data/ok/00-Cargo.toml.gz");
+    let expected = ripgzip::decompress_to_vec(gzip).unwrap();
+
+    /* Strip the plain 10-byte header, keep the footer as trailing data. */
+    let mut raw = &gzip[10..];
+    let mut output = vec![];
+    assert_eq!(ripgzip::inflate_raw(&mut raw, &mut output).unwrap(), 295);
+    assert_eq!(output, expected);
+    assert_eq!(raw, &gzip[gzip.len() - 8..]);
+}
+
+#[test]
+fn deflate_reader_into_inner() {
+    use ripgzip::{BitReader, DeflateReader};
+
+    let gzip: &[u8] = include_bytes!("../data/ok/00-Cargo.toml.gz");
+    let mut reader = DeflateReader::new(BitReader::new(&gzip[10..]));
+    let (byte_count, (_, output)) = reader.deflate(vec![]).unwrap();
+    assert_eq!(byte_count, 295);
+    assert_eq!(output, ripgzip::decompress_to_vec(gzip).unwrap());
+    /* The footer follows the stream, from the byte after the padding of its last block. */
+    let deflate_len = gzip.len() as u64 - 18;
+    assert_eq!(reader.bit_position().div_ceil(8), deflate_len);
+    assert_eq!(reader.stats().bytes_in, deflate_len);
+    let rest = reader.into_inner().unwrap();
+    assert_eq!(rest, &gzip[gzip.len() - 8..]);
+
+    /* The position of the bit reader counts bits across byte boundaries. */
+    let mut bit_reader = BitReader::new(gzip);
+    bit_reader.read_bits(5).unwrap();
+    bit_reader.read_bits(12).unwrap();
+    assert_eq!(bit_reader.bit_position(), 17);
+    assert_eq!(bit_reader.into_inner().unwrap(), &gzip[3..]);
+}
+
+#[test]
+fn deflate_reader_deflate_into() {
+    use ripgzip::{BitReader, CompressionLevel, DeflateReader};
+
+    let mut raw = vec![];
+    ripgzip::deflate_raw(b"first, ", &mut raw, CompressionLevel::Store).unwrap();
+    let first_len = raw.len();
+    ripgzip::deflate_raw(b"second", &mut raw, CompressionLevel::Fixed).unwrap();
+
+    /* Both streams go to the same sink, which is still owned afterwards. */
+    let mut output = vec![];
+    let mut reader = DeflateReader::new(BitReader::new(&raw[..first_len]));
+    assert_eq!(reader.deflate_into(&mut output).unwrap().0, 7);
+    let mut reader = DeflateReader::new(BitReader::new(&raw[first_len..]));
+    let (byte_count, crc32) = reader.deflate_into(&mut output).unwrap();
+    assert_eq!(byte_count, 6);
+    let crc = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+    assert_eq!(crc32, crc.checksum(b"second"));
+    output.extend_from_slice(b"!");
+    assert_eq!(output, b"first, second!");
+}
+
+#[test]
+fn deflate_reader_reused_tables() {
+    use ripgzip::{BitReader, DeflateReader};
+
+    let data: &[u8] = include_bytes!("../data/ok/05-app.gz");
+    let mut reader = DeflateReader::new(BitReader::new(&data[10..])).with_reused_tables();
+    let mut output = vec![];
+    reader.deflate_into(&mut output).unwrap();
+    assert!(reader.stats().dynamic_blocks > 10);
+    assert_eq!(output, ripgzip::decompress_slice(data).unwrap());
+}
+
+#[test]
+fn deflate_reader_reset() {
+    use ripgzip::{BitReader, DeflateReader};
+
+    let first: &[u8] = include_bytes!("../data/ok/00-Cargo.toml.gz");
+    let second: &[u8] = include_bytes!("../data/ok/01-page.gz");
+    let mut reader = DeflateReader::new(BitReader::new(&first[10..]));
+    let mut output = vec![];
+    reader.deflate_into(&mut output).unwrap();
+    let second_raw = &second[10..];
+    reader.reset(BitReader::new(second_raw));
+    let (byte_count, _) = reader.deflate_into(&mut output).unwrap();
+
+    let mut fresh = vec![];
+    let mut fresh_reader = DeflateReader::new(BitReader::new(second_raw));
+    assert_eq!(fresh_reader.deflate_into(&mut fresh).unwrap().0, byte_count);
+    assert_eq!(reader.stats(), fresh_reader.stats());
+    assert_eq!(
+        output,
+        [first, second]
+            .map(|data| ripgzip::decompress_to_vec(data).unwrap())
+            .concat()
+    );
+}
+
+#[test]
+fn gzip_decoder() {
+    use std::io::{BufReader, ErrorKind, Read};
+
+    let data: &[u8] = include_bytes!("../data/ok/11-named-concat.gz");
+    let mut output = String::new();
+    ripgzip::GzipDecoder::new(data)
+        .read_to_string(&mut output)
+        .unwrap();
+    assert_eq!(output, "Hello, world!\nSecond member.\n");
+
+    let data: &[u8] = include_bytes!("../data/ok/09-concat.gz");
+    let expected = ripgzip::decompress_to_vec(data).unwrap();
+    let mut decoder = ripgzip::GzipDecoder::new(BufReader::with_capacity(3, data));
+    let mut output = vec![];
+    let mut buf = [0; 1000];
+    loop {
+        let len = decoder.read(&mut buf).unwrap();
+        if len == 0 {
+            break;
+        }
+        output.extend_from_slice(&buf[..len]);
+    }
+    assert_eq!(output, expected);
+
+    let data: &[u8] = include_bytes!("../data/ok/06-war-and-peace.txt.gz");
+    let mut decoder = ripgzip::GzipDecoder::new(data);
+    assert_eq!(decoder.current_crc32(), None);
+    decoder.read_exact(&mut buf).unwrap();
+    assert!(decoder.current_crc32().is_some());
+    decoder.read_to_end(&mut vec![]).unwrap();
+    assert_eq!(decoder.current_crc32(), None);
+
+    let data: &[u8] = include_bytes!("../data/corrupted/01-bad-crc32.gz");
+    let err = ripgzip::GzipDecoder::new(data)
+        .read_to_end(&mut vec![])
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert_eq!(
+        err.to_string(),
+        "crc32 check failed: the footer has 0x273c0e07, the data has 0x271c0e07"
+    );
+
+    let data: &[u8] = include_bytes!("../data/corrupted/02-unexpected-eof.gz");
+    let err = ripgzip::GzipDecoder::new(data)
+        .read_to_end(&mut vec![])
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn decompress_with_progress() {
+    let data: &[u8] = include_bytes!("../data/ok/09-concat.gz");
+    let mut reported = vec![];
+    ripgzip::decompress_with_progress(data, std::io::sink(), |count| reported.push(count)).unwrap();
+    assert!(reported.len() > 10);
+    assert!(reported.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(reported.last(), Some(&1784657));
+
+    let data: &[u8] = include_bytes!("../data/corrupted/01-bad-crc32.gz");
+    let mut reported = vec![];
+    assert!(
+        ripgzip::decompress_with_progress(data, std::io::sink(), |count| reported.push(count))
+            .is_err()
+    );
+    assert!(reported.is_empty());
+}
+
+#[test]
+fn concatenated_members() {
+    use std::io::{self, BufRead, BufReader, Read};
+
+    let first: &[u8] = include_bytes!("../data/ok/00-Cargo.toml.gz");
+    let second: &[u8] = include_bytes!("../data/ok/10-header-crc16.gz");
+    let concat = [first, second].concat();
+
+    let mut expected = ripgzip::decompress_to_vec(first).unwrap();
+    expected.extend(ripgzip::decompress_to_vec(second).unwrap());
+
+    for capacity in [1, 2, 3, 7, 8192] {
+        let mut output = vec![];
+        let headers = ripgzip::decompress_with_headers(
+            BufReader::with_capacity(capacity, concat.as_slice()),
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(headers.len(), 2);
+        assert_eq!(output, expected);
+    }
+
+    /* The footer of every member is checked, not only the last one. */
+    for footer_byte in [first.len() - 8, first.len() - 1] {
+        let mut corrupted = concat.clone();
+        corrupted[footer_byte] ^= 1;
+        assert!(ripgzip::decompress(corrupted.as_slice(), io::sink()).is_err());
+    }
+
+    /* An i/o error between members must not look like the end of input. */
+    struct FailingReader<'a>(&'a [u8]);
+
+    impl Read for FailingReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let available = self.fill_buf()?;
+            let len = available.len().min(buf.len());
+            buf[..len].copy_from_slice(&available[..len]);
+            self.consume(len);
+            Ok(len)
+        }
+    }
+
+    impl BufRead for FailingReader<'_> {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            if self.0.is_empty() {
+                return Err(io::Error::other("connection reset"));
+            }
+            Ok(self.0)
+        }
+
+        fn consume(&mut self, amt: usize) {
+            self.0 = &self.0[amt..];
+        }
+    }
+
+    let err = ripgzip::decompress(FailingReader(first), io::sink()).unwrap_err();
+    assert!(err.to_string().contains("connection reset"));
+}
+
+#[test]
+fn decompress_allow_trailing() {
+    use std::io::BufReader;
+
+    let data: &[u8] = include_bytes!("../data/ok/11-named-concat.gz");
+    for trailer in [&b"\0\0\0\0"[..], b"garbage", b"\n"] {
+        let input = [data, trailer].concat();
+        assert!(ripgzip::decompress(input.as_slice(), std::io::sink()).is_err());
+
+        let mut input = BufReader::with_capacity(1, input.as_slice());
+        let mut output = vec![];
+        ripgzip::decompress_allow_trailing(&mut input, &mut output).unwrap();
+        assert_eq!(output, b"Hello, world!\nSecond member.\n");
+        assert_eq!(input.buffer().first(), trailer.first());
+    }
+
+    /* ID1 after a member starts another one, which has to be valid. */
+    for trailer in [&b"\x1f"[..], b"\x1f\x00", b"\x1f\x8b\x08"] {
+        let input = [data, trailer].concat();
+        assert!(ripgzip::decompress_allow_trailing(input.as_slice(), std::io::sink()).is_err());
+    }
+
+    /* Truncated members and garbage instead of the first member are still errors. */
+    assert!(ripgzip::decompress_allow_trailing(&data[..data.len() - 1], std::io::sink()).is_err());
+    assert!(ripgzip::decompress_allow_trailing(&b"garbage"[..], std::io::sink()).is_err());
+}
+
+#[test]
+fn decompress_reader() {
+    let file = std::fs::File::open("data/ok/09-concat.gz").unwrap();
+    let mut output = vec![];
+    ripgzip::decompress_reader(file, &mut output).unwrap();
+
+    let data: &[u8] = include_bytes!("../data/ok/09-concat.gz");
+    assert_eq!(output, ripgzip::decompress_to_vec(data).unwrap());
+}
+
+#[test]
+fn verify() {
+    let data: &[u8] = include_bytes!("../data/ok/09-concat.gz");
+    let footers = ripgzip::verify(data).unwrap();
+    let sizes: Vec<u32> = footers.iter().map(|footer| footer.data_size).collect();
+    assert_eq!(sizes, [88194, 153333, 1543130]);
+
+    let data: &[u8] = include_bytes!("../data/ok/11-named-concat.gz");
+    let footers = ripgzip::verify(data).unwrap();
+    assert_eq!(footers.len(), 2);
+    assert_eq!(footers[0].data_size, 14);
+    assert_eq!(
+        footers[0].data_crc32,
+        crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC).checksum(b"Hello, world!\n")
+    );
+
+    let data: &[u8] = include_bytes!("../data/corrupted/00-bad-length.gz");
+    assert!(matches!(
+        ripgzip::verify(data),
+        Err(ripgzip::DecodeError::LengthMismatch { .. })
+    ));
+}
+
+#[test]
+fn decompress_seekable_to_vec() {
+    use std::io::{Cursor, Seek, SeekFrom};
+
+    let data: &[u8] = include_bytes!("../data/ok/06-war-and-peace.txt.gz");
+    let output = ripgzip::decompress_seekable_to_vec(Cursor::new(data)).unwrap();
+    assert_eq!(output.len(), 1543130);
+    assert_eq!(output.capacity(), 1543130);
+
+    /* The hint covers the data from the current position on. */
+    let input = [b"junk".as_slice(), data].concat();
+    let mut cursor = Cursor::new(input.as_slice());
+    cursor.seek(SeekFrom::Start(4)).unwrap();
+    assert_eq!(ripgzip::decompress_seekable_to_vec(cursor).unwrap(), output);
+
+    assert!(ripgzip::decompress_seekable_to_vec(Cursor::new(&data[..3])).is_err());
+}
+
+#[test]
+fn decompress_seekable_to_vec_large() {
+    use std::io::Cursor;
+
+    /* Past the part of the hint allocated up front. */
+    let data: Vec<u8> = (0..65 << 20).map(|i: u32| (i % 251) as u8).collect();
+    let mut gzip = vec![];
+    ripgzip::store(&data, &mut gzip).unwrap();
+    let output = ripgzip::decompress_seekable_to_vec(Cursor::new(&gzip)).unwrap();
+    assert_eq!(output.capacity(), data.len());
+    assert!(output == data);
+
+    /* With several members, ISIZE is only the size of the last one. */
+    let mut first = vec![];
+    ripgzip::store(b"first member", &mut first).unwrap();
+    let input = [first.as_slice(), &gzip].concat();
+    let output = ripgzip::decompress_seekable_to_vec(Cursor::new(&input)).unwrap();
+    assert_eq!(output.len(), data.len() + 12);
+    assert!(output.ends_with(&data));
+}
+
+#[test]
+fn decompress_windowed() {
+    let data: &[u8] = include_bytes!("../data/ok/09-concat.gz");
+    let mut output = vec![];
+    let mut max_chunk = 0;
+    ripgzip::decompress_windowed(data, |chunk| {
+        assert!(!chunk.is_empty());
+        max_chunk = max_chunk.max(chunk.len());
+        output.extend_from_slice(chunk);
+    })
+    .unwrap();
+    assert_eq!(output, ripgzip::decompress_slice(data).unwrap());
+    assert_eq!(max_chunk, 16 * 1024);
+}
+
+#[test]
+fn chunk_reader() {
+    use ripgzip::ChunkReader;
+
+    let data: &[u8] = include_bytes!("../data/ok/09-concat.gz");
+    let expected = ripgzip::decompress_slice(data).unwrap();
+    for chunk_size in [1, 2, 3, 7, 4096] {
+        let chunks = data.chunks(chunk_size).map(<[u8]>::to_vec);
+        let mut output = vec![];
+        ripgzip::decompress(ChunkReader::new(chunks), &mut output).unwrap();
+        assert_eq!(output, expected, "chunks of {} bytes", chunk_size);
+    }
+}
+
+#[test]
+fn decompress_with_footers() {
+    let data: &[u8] = include_bytes!("../data/ok/11-named-concat.gz");
+    let mut output = vec![];
+    let footers = ripgzip::decompress_with_footers(data, &mut output).unwrap();
+    let crc = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+    assert_eq!(footers.len(), 2);
+    assert_eq!(
+        footers
+            .iter()
+            .map(|footer| footer.data_size as usize)
+            .sum::<usize>(),
+        output.len()
+    );
+    assert_eq!(footers[0].data_crc32, crc.checksum(&output[..14]));
+    assert_eq!(footers[1].data_crc32, crc.checksum(&output[14..]));
+}
+
+#[test]
+fn inflate_raw_with_blocks() {
+    use ripgzip::{BlockInfo, CompressionType};
+
+    /* A stored block, then a final fixed block holding "a". */
+    let raw: &[u8] = &[0, 3, 0, !3, !0, b'a', b'b', b'c', 0x4b, 0x04, 0x00];
+    let mut output = vec![];
+    let blocks = ripgzip::inflate_raw_with_blocks(raw, &mut output).unwrap();
+    assert_eq!(output, b"abca");
+    assert_eq!(
+        blocks,
+        [
+            BlockInfo {
+                bit_offset: 0,
+                compression_type: CompressionType::Uncompressed,
+                is_final: false,
+            },
+            BlockInfo {
+                bit_offset: 64,
+                compression_type: CompressionType::FixedTree,
+                is_final: true,
+            },
+        ]
+    );
+}
+
+#[test]
+fn inflate_raw_with_stats() {
+    use std::io::BufReader;
+
+    let gzip: &[u8] = include_bytes!("../data/ok/00-Cargo.toml.gz");
+    let stats = ripgzip::inflate_raw_with_stats(&gzip[10..], std::io::sink()).unwrap();
+    assert_eq!(stats.bytes_in as usize, gzip.len() - 18);
+    assert_eq!(stats.bytes_out, 295);
+    assert_eq!(stats.stored_blocks, 0);
+    assert_eq!(stats.fixed_blocks + stats.dynamic_blocks, 1);
+
+    /* Two stored blocks, the second one being the last. */
+    let mut raw = vec![0, 3, 0, !3, !0];
+    raw.extend(b"abc");
+    raw.extend([1, 2, 0, !2, !0]);
+    raw.extend(b"de");
+    raw.extend(b"trailer");
+    for capacity in [1, 3, 8192] {
+        let mut input = BufReader::with_capacity(capacity, raw.as_slice());
+        let mut output = vec![];
+        let stats = ripgzip::inflate_raw_with_stats(&mut input, &mut output).unwrap();
+        assert_eq!(output, b"abcde");
+        assert_eq!(
+            stats,
+            ripgzip::DeflateStats {
+                stored_blocks: 2,
+                fixed_blocks: 0,
+                dynamic_blocks: 0,
+                bytes_in: 15,
+                bytes_out: 5,
+            }
+        );
+    }
+}
+
+#[test]
+fn decompress_max_members() {
+    let data: &[u8] = include_bytes!("../data/ok/09-concat.gz");
+    let mut output = vec![];
+    ripgzip::decompress_max_members(data, &mut output, 3).unwrap();
+    assert_eq!(output.len(), 1784657);
+
+    let mut output = vec![];
+    assert!(matches!(
+        ripgzip::decompress_max_members(data, &mut output, 2),
+        Err(ripgzip::DecodeError::TooManyMembers)
+    ));
+    assert_eq!(output.len(), 88194 + 153333);
+
+    assert!(ripgzip::decompress_max_members(&b""[..], std::io::sink(), 0).is_ok());
+}
+
+#[test]
+fn inflate_raw_with_window_size() {
+    /* A period of 300 bytes: every match is 300 bytes back. */
+    let data: Vec<u8> = (0..10_000u32).map(|i| (i % 300 * 7 % 256) as u8).collect();
+    let mut gzip = vec![];
+    ripgzip::compress(data.as_slice(), &mut gzip, ripgzip::CompressionLevel::Fixed).unwrap();
+    let raw = &gzip[10..gzip.len() - 8];
+
+    for window_size in [512, 32768] {
+        let mut output = vec![];
+        let len = ripgzip::inflate_raw_with_window_size(raw, &mut output, window_size).unwrap();
+        assert_eq!(len as usize, data.len());
+        assert_eq!(output, data);
+    }
+
+    let err = ripgzip::inflate_raw_with_window_size(raw, std::io::sink(), 256).unwrap_err();
+    assert!(matches!(
+        err,
+        ripgzip::DecodeError::DistanceTooFar {
+            distance: 300,
+            window_size: 256
+        }
+    ));
+    assert_eq!(
+        err.to_string(),
+        "distance 300 exceeds the window of 256 bytes"
+    );
+}
+
+#[test]
+fn decompressor() {
+    use ripgzip::{DecodeError, Decompressor};
+
+    let data: &[u8] = include_bytes!("../data/ok/11-named-concat.gz");
+    let input = [data, b"trailer"].concat();
+    let mut reported = vec![];
+    let mut output = vec![];
+    Decompressor::new()
+        .max_output(29)
+        .max_members(2)
+        .allow_trailing(true)
+        .on_progress(|count| reported.push(count))
+        .run(input.as_slice(), &mut output)
+        .unwrap();
+    assert_eq!(output, b"Hello, world!\nSecond member.\n");
+    assert_eq!(reported, [29]);
+
+    let mut decompressor = Decompressor::new().max_output(28);
+    assert!(decompressor.run(data, std::io::sink()).is_err());
+    assert!(decompressor
+        .max_output(29)
+        .run(data, std::io::sink())
+        .is_ok());
+
+    assert!(matches!(
+        Decompressor::default()
+            .max_members(1)
+            .run(data, std::io::sink()),
+        Err(DecodeError::TooManyMembers)
+    ));
+    assert!(Decompressor::new()
+        .run(input.as_slice(), std::io::sink())
+        .is_err());
+}
+
+#[test]
+fn decode_state() {
+    use ripgzip::{DecodeError, DecodeState};
+
+    let files: [&[u8]; 3] = [
+        include_bytes!("../data/ok/09-concat.gz"),
+        include_bytes!("../data/ok/10-header-crc16.gz"),
+        include_bytes!("../data/ok/11-named-concat.gz"),
+    ];
+    for data in files {
+        let expected = ripgzip::decompress_to_vec(data).unwrap();
+        for chunk_size in [1, 7, 1000, data.len()] {
+            let mut state = DecodeState::new();
+            let mut output = vec![];
+            for chunk in data.chunks(chunk_size) {
+                output.extend(state.push(chunk).unwrap());
+            }
+            output.extend(state.finish().unwrap());
+            assert_eq!(output, expected);
+        }
+    }
+
+    /* Most of the data comes out before the end of the input. */
+    let data: &[u8] = include_bytes!("../data/ok/06-war-and-peace.txt.gz");
+    let mut state = DecodeState::new();
+    let output = state.push(&data[..data.len() / 2]).unwrap();
+    assert!(output.len() > 700_000);
+
+    let mut state = DecodeState::new();
+    state.push(&data[..data.len() - 1]).unwrap();
+    assert!(matches!(
+        state.finish(),
+        Err(DecodeError::UnexpectedEof { .. })
+    ));
+
+    let data: &[u8] = include_bytes!("../data/corrupted/01-bad-crc32.gz");
+    let mut state = DecodeState::default();
+    assert!(matches!(
+        state.push(data).and_then(|_| state.finish()),
+        Err(DecodeError::CrcMismatch { .. })
+    ));
+
+    let data: &[u8] = include_bytes!("../data/corrupted/03-wrong-id.gz");
+    let mut state = DecodeState::new();
+    assert!(matches!(state.push(data), Err(DecodeError::BadMagic)));
+    assert!(state.push(data).is_err());
+}
+
+#[test]
+fn decompressor_without_crc() {
+    use ripgzip::{DecodeError, Decompressor};
+
+    /*
+     * Skipping the CRC-32 takes decoding of 06-war-and-peace.txt.gz in a release
+     * build from about 23 ms down to 20 ms.
+     */
+    let data: &[u8] = include_bytes!("../data/corrupted/01-bad-crc32.gz");
+    let mut output = vec![];
+    Decompressor::new()
+        .verify_crc(false)
+        .run(data, &mut output)
+        .unwrap();
+    assert!(!output.is_empty());
+    assert!(matches!(
+        Decompressor::new().run(data, std::io::sink()),
+        Err(DecodeError::CrcMismatch { .. })
+    ));
+
+    let data: &[u8] = include_bytes!("../data/corrupted/00-bad-length.gz");
+    assert!(matches!(
+        Decompressor::new()
+            .verify_crc(false)
+            .run(data, std::io::sink()),
+        Err(DecodeError::LengthMismatch { .. })
+    ));
+}
+
+#[test]
+fn empty_members() {
+    use ripgzip::DecodeError;
+    use std::io::Read;
+
+    /* As written by `gzip -n` for empty input: a final fixed block with just EOB. */
+    let header: &[u8] = &[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 3];
+    let empty = [header, &[0x03, 0x00], &[0; 8]].concat();
+    let empty_stored = [header, &[0x01, 0x00, 0x00, 0xff, 0xff], &[0; 8]].concat();
+
+    assert_eq!(ripgzip::decompress_to_vec(empty.as_slice()).unwrap(), b"");
+    assert_eq!(
+        ripgzip::decompress_to_vec(empty_stored.as_slice()).unwrap(),
+        b""
+    );
+
+    let data: &[u8] = include_bytes!("../data/ok/11-named-concat.gz");
+    let input = [&empty, data, &empty_stored, data, &empty].concat();
+    let headers = ripgzip::decompress_with_headers(input.as_slice(), std::io::sink()).unwrap();
+    assert_eq!(headers.len(), 7);
+    assert_eq!(
+        ripgzip::decompress_to_vec(input.as_slice()).unwrap(),
+        b"Hello, world!\nSecond member.\n".repeat(2)
+    );
+    let mut output = vec![];
+    ripgzip::GzipDecoder::new(input.as_slice())
+        .read_to_end(&mut output)
+        .unwrap();
+    assert_eq!(output, b"Hello, world!\nSecond member.\n".repeat(2));
+
+    let mut bad_crc = empty.clone();
+    bad_crc[12] = 1;
+    assert!(matches!(
+        ripgzip::decompress(bad_crc.as_slice(), std::io::sink()),
+        Err(DecodeError::CrcMismatch { .. })
+    ));
+    let mut bad_size = empty;
+    bad_size[16] = 1;
+    assert!(matches!(
+        ripgzip::decompress(bad_size.as_slice(), std::io::sink()),
+        Err(DecodeError::LengthMismatch { .. })
+    ));
+}
+
+#[test]
+fn decompress_from_slice() {
+    use ripgzip::DecodeError;
+
+    let data: &[u8] = include_bytes!("../data/ok/06-war-and-peace.txt.gz");
+    let expected = ripgzip::decompress_to_vec(data).unwrap();
+    let (output, result) = ripgzip::decompress_from_slice(data);
+    assert!(result.is_ok());
+    assert_eq!(output, expected);
+
+    let (output, result) = ripgzip::decompress_from_slice(&data[..data.len() / 2]);
+    assert!(matches!(result, Err(DecodeError::UnexpectedEof { .. })));
+    assert!(output.len() > 700_000);
+    assert!(expected.starts_with(&output));
+
+    let data: &[u8] = include_bytes!("../data/corrupted/01-bad-crc32.gz");
+    let (output, result) = ripgzip::decompress_from_slice(data);
+    assert!(matches!(result, Err(DecodeError::CrcMismatch { .. })));
+    assert!(!output.is_empty());
+
+    let (output, result) = ripgzip::decompress_from_slice(b"garbage");
+    assert!(matches!(result, Err(DecodeError::BadMagic)));
+    assert!(output.is_empty());
+}
+
+#[test]
+fn decompressor_without_size() {
+    use ripgzip::{DecodeError, Decompressor};
+
+    let data: &[u8] = include_bytes!("../data/ok/11-named-concat.gz");
+    let mut bad_size = data.to_vec();
+    let len = bad_size.len();
+    bad_size[len - 4] ^= 1;
+
+    let mut output = vec![];
+    Decompressor::new()
+        .verify_size(false)
+        .run(bad_size.as_slice(), &mut output)
+        .unwrap();
+    assert_eq!(output, b"Hello, world!\nSecond member.\n");
+    assert!(matches!(
+        Decompressor::new().run(bad_size.as_slice(), std::io::sink()),
+        Err(DecodeError::LengthMismatch { .. })
+    ));
+
+    /* The CRC-32 is still checked, and with it off the size has to be right. */
+    let mut bad_crc = bad_size.clone();
+    bad_crc[len - 8] ^= 1;
+    assert!(matches!(
+        Decompressor::new()
+            .verify_size(false)
+            .run(bad_crc.as_slice(), std::io::sink()),
+        Err(DecodeError::CrcMismatch { .. })
+    ));
+    assert!(matches!(
+        Decompressor::new()
+            .verify_size(false)
+            .verify_crc(false)
+            .run(bad_size.as_slice(), std::io::sink()),
+        Err(DecodeError::LengthMismatch { .. })
+    ));
+}
+
+#[test]
+fn decompressor_strict_padding() {
+    use ripgzip::{DecodeError, Decompressor};
+
+    let data: &[u8] = include_bytes!("../data/ok/09-concat.gz");
+    Decompressor::new()
+        .strict_padding(true)
+        .run(data, std::io::sink())
+        .unwrap();
+
+    /* The top bit of the last deflate byte only pads the final block. */
+    let data: &[u8] = include_bytes!("../data/ok/00-Cargo.toml.gz");
+    let mut bad_padding = data.to_vec();
+    let len = bad_padding.len();
+    bad_padding[len - 9] |= 0x80;
+    let mut output = vec![];
+    Decompressor::new()
+        .run(bad_padding.as_slice(), &mut output)
+        .unwrap();
+    assert_eq!(output.len(), 295);
+    assert!(matches!(
+        Decompressor::new()
+            .strict_padding(true)
+            .run(bad_padding.as_slice(), std::io::sink()),
+        Err(DecodeError::NonZeroPadding(_))
+    ));
+}
+
+#[test]
+fn decompressor_tree_cache() {
+    use ripgzip::Decompressor;
+
+    let data: &[u8] = include_bytes!("../data/ok/05-app.gz");
+    let mut output = vec![];
+    Decompressor::new()
+        .tree_cache(4)
+        .run(data, &mut output)
+        .unwrap();
+    assert_eq!(output, ripgzip::decompress_slice(data).unwrap());
+}
+
+#[test]
+fn decompress_parallel() {
+    use ripgzip::DecodeError;
+    use std::io::Cursor;
+
+    /* A stored member whose data looks like the start of another member. */
+    let mut fake_start = vec![];
+    ripgzip::store(b"\x1f\x8b\x08\x00 not a member", &mut fake_start).unwrap();
+    let members: [&[u8]; 4] = [
+        include_bytes!("../data/ok/09-concat.gz"),
+        &fake_start,
+        include_bytes!("../data/ok/01-page.gz"),
+        include_bytes!("../data/ok/11-named-concat.gz"),
+    ];
+    let input = members.concat();
+    let expected = ripgzip::decompress_to_vec(input.as_slice()).unwrap();
+
+    for threads in [0, 1, 4] {
+        let mut output = vec![];
+        /* The input needn't be seekable. */
+        ripgzip::decompress_parallel(input.as_slice(), &mut output, threads).unwrap();
+        assert_eq!(output, expected);
+    }
+
+    /* Decoding starts at the current position. */
+    let mut cursor = Cursor::new(&input);
+    cursor.set_position(members[0].len() as u64);
+    let mut output = vec![];
+    ripgzip::decompress_parallel(cursor, &mut output, 2).unwrap();
+    assert!(expected.ends_with(&output));
+    assert!(output.starts_with(b"\x1f\x8b\x08\x00 not a member"));
+
+    let mut output = vec![];
+    ripgzip::decompress_parallel(Cursor::new(b""), &mut output, 2).unwrap();
+    assert!(output.is_empty());
+
+    /* Members before the broken one are written, as with `decompress`. */
+    let mut corrupted = input.clone();
+    corrupted[members[0].len() + members[1].len() + 100] ^= 0xff;
+    let mut output = vec![];
+    assert!(ripgzip::decompress_parallel(Cursor::new(&corrupted), &mut output, 4).is_err());
+    let before = ripgzip::decompress_to_vec([members[0], members[1]].concat().as_slice());
+    assert_eq!(output, before.unwrap());
+
+    let garbage = [input.as_slice(), b"garbage"].concat();
+    assert!(matches!(
+        ripgzip::decompress_parallel(Cursor::new(&garbage), std::io::sink(), 4),
+        Err(DecodeError::BadMagic)
+    ));
+
+    /* Stored data with what looks like a footer and a header, which is split at. */
+    let mut fake_footer = vec![];
+    let data = b"twenty bytes of data\0\0\0\0\x1f\x8b\x08\0\0\0\0\0\0\xff rest";
+    ripgzip::store(data, &mut fake_footer).unwrap();
+    let input = [members[0], &fake_footer, members[2], &fake_footer].concat();
+    let mut output = vec![];
+    ripgzip::decompress_parallel(Cursor::new(&input), &mut output, 4).unwrap();
+    assert_eq!(
+        output,
+        ripgzip::decompress_to_vec(input.as_slice()).unwrap()
+    );
+
+    let data: &[u8] = include_bytes!("../data/ok/07-war-and-peace.txt.gz.gz");
+    let mut output = vec![];
+    ripgzip::decompress_parallel(Cursor::new(data), &mut output, 4).unwrap();
+    assert_eq!(output, ripgzip::decompress_slice(data).unwrap());
+}
+
+#[test]
+fn decompressor_normalize_line_endings() {
+    use ripgzip::{CompressionLevel, Decompressor, GzipWriter, MemberHeader};
+
+    let compress = |data: &[u8], is_text| {
+        let header = MemberHeader {
+            is_text,
+            ..Default::default()
+        };
+        let (_, compressed) = GzipWriter::new(vec![], header)
+            .compress(data, CompressionLevel::Fixed)
+            .unwrap();
+        compressed
+    };
+    let input = [
+        compress(b"one\r\ntwo\r", true),
+        compress(b"\nbinary\r\n", false),
+        compress(b"three\r\n", true),
+    ]
+    .concat();
+
+    let mut output = vec![];
+    Decompressor::new()
+        .normalize_line_endings(true)
+        .run(input.as_slice(), &mut output)
+        .unwrap();
+    assert_eq!(output, b"one\ntwo\r\nbinary\r\nthree\n");
+
+    let mut output = vec![];
+    Decompressor::new()
+        .run(input.as_slice(), &mut output)
+        .unwrap();
+    assert_eq!(output, b"one\r\ntwo\r\nbinary\r\nthree\r\n");
+}
+
+#[test]
+fn decompressor_skip_to_magic() {
+    use ripgzip::{DecodeError, Decompressor};
+
+    let data: &[u8] = include_bytes!("../data/ok/11-named-concat.gz");
+    let input = [&b"#!/bin/sh\nexit 0\n\x1f"[..], data].concat();
+
+    let mut output = vec![];
+    Decompressor::new()
+        .skip_to_magic(1024)
+        .run(input.as_slice(), &mut output)
+        .unwrap();
+    assert_eq!(output, b"Hello, world!\nSecond member.\n");
+
+    /* The stray ID1 and the magic end buffers of the reader. */
+    let mut output = vec![];
+    Decompressor::new()
+        .skip_to_magic(1024)
+        .run(
+            std::io::BufReader::with_capacity(2, input.as_slice()),
+            &mut output,
+        )
+        .unwrap();
+    assert_eq!(output, b"Hello, world!\nSecond member.\n");
+
+    assert!(matches!(
+        Decompressor::new().run(input.as_slice(), std::io::sink()),
+        Err(DecodeError::BadMagic)
+    ));
+    assert!(matches!(
+        Decompressor::new()
+            .skip_to_magic(10)
+            .run(input.as_slice(), std::io::sink()),
+        Err(DecodeError::BadMagic)
+    ));
+}
+
+#[test]
+fn decompress_lenient() {
+    use ripgzip::DecodeError;
+
+    let good: &[u8] = include_bytes!("../data/ok/00-Cargo.toml.gz");
+    let bad_crc: &[u8] = include_bytes!("../data/corrupted/01-bad-crc32.gz");
+    let named: &[u8] = include_bytes!("../data/ok/11-named-concat.gz");
+    let input = [good, bad_crc, b"garbage", named].concat();
+
+    let mut output = vec![];
+    let outcomes = ripgzip::decompress_lenient(input.as_slice(), &mut output).unwrap();
+    let offsets: Vec<u64> = outcomes.iter().map(|outcome| outcome.offset).collect();
+    /* The garbage is skipped while looking for the member after the corrupt one. */
+    let named_start = (good.len() + bad_crc.len() + 7) as u64;
+    let first_named_len = ripgzip::index(named).unwrap()[0].compressed_len;
+    assert_eq!(
+        offsets,
+        [
+            0,
+            good.len() as u64,
+            named_start,
+            named_start + first_named_len
+        ]
+    );
+
+    assert_eq!(outcomes[0].result.as_ref().unwrap().uncompressed_len, 295);
+    assert!(matches!(
+        outcomes[1].result,
+        Err(DecodeError::CrcMismatch { .. })
+    ));
+    assert!(outcomes[2].result.is_ok());
+    assert!(outcomes[3].result.is_ok());
+
+    /* Data before a corrupt first member is reported too. */
+    let input = [&b"garbage"[..], good].concat();
+    let outcomes = ripgzip::decompress_lenient(input.as_slice(), std::io::sink()).unwrap();
+    assert_eq!(outcomes.len(), 2);
+    assert!(matches!(outcomes[0].result, Err(DecodeError::BadMagic)));
+    assert_eq!(outcomes[1].offset, 7);
+
+    assert!(output.starts_with(&ripgzip::decompress_to_vec(good).unwrap()));
+    assert!(output.ends_with(b"Hello, world!\nSecond member.\n"));
+
+    /* A truncated last member is reported as well. */
+    let outcomes = ripgzip::decompress_lenient(&good[..100], std::io::sink()).unwrap();
+    assert_eq!(outcomes.len(), 1);
+    assert!(matches!(
+        outcomes[0].result,
+        Err(DecodeError::UnexpectedEof { .. })
+    ));
+}
@@ -1,12 +1,17 @@
+use std::error::Error;
+
 fn check_decompression_error(mut data: &[u8], msg: &'static str) {
     let res = ripgzip::decompress(&mut data, &mut std::io::sink());
     if res.is_ok() {
         panic!("expected Err, got Ok");
     }
-    for inner in res.unwrap_err().chain() {
-        if inner.to_string().contains(msg) {
+    let err = res.unwrap_err();
+    let mut inner: Option<&dyn Error> = Some(&err);
+    while let Some(cause) = inner {
+        if cause.to_string().contains(msg) {
             return;
         }
+        inner = cause.source();
     }
     panic!("error does not contain message: {}", msg);
 }
@@ -44,3 +49,115 @@ fn errors() {
         "nlen check failed",
     );
 }
+
+#[test]
+fn typed_errors() {
+    use ripgzip::{DecodeError, EofContext};
+
+    let decompress = |mut data: &[u8]| ripgzip::decompress(&mut data, std::io::sink());
+    assert!(matches!(
+        decompress(include_bytes!("../data/corrupted/00-bad-length.gz")),
+        Err(DecodeError::LengthMismatch { .. })
+    ));
+    assert!(matches!(
+        decompress(include_bytes!("../data/corrupted/01-bad-crc32.gz")),
+        Err(DecodeError::CrcMismatch { .. })
+    ));
+    assert!(matches!(
+        decompress(include_bytes!("../data/corrupted/02-unexpected-eof.gz")),
+        Err(DecodeError::UnexpectedEof {
+            context: EofContext::Body
+        })
+    ));
+    let data = include_bytes!("../data/ok/00-Cargo.toml.gz");
+    assert!(matches!(
+        decompress(&data[..data.len() - 3]),
+        Err(DecodeError::UnexpectedEof {
+            context: EofContext::Footer
+        })
+    ));
+    assert!(matches!(
+        decompress(include_bytes!("../data/corrupted/04-header-eof.gz")),
+        Err(DecodeError::UnexpectedEof {
+            context: EofContext::Header
+        })
+    ));
+    assert!(matches!(
+        decompress(include_bytes!("../data/corrupted/03-wrong-id.gz")),
+        Err(DecodeError::BadMagic)
+    ));
+    assert!(matches!(
+        decompress(include_bytes!("../data/corrupted/05-bad-header-crc16.gz")),
+        Err(DecodeError::HeaderCrcMismatch)
+    ));
+    assert!(matches!(
+        decompress(include_bytes!("../data/corrupted/06-invalid-btype.gz")),
+        Err(DecodeError::ReservedBlockType {
+            block_index: 0,
+            bit_offset: 0
+        })
+    ));
+    assert!(matches!(
+        decompress(include_bytes!("../data/corrupted/07-invalid-cm.gz")),
+        Err(DecodeError::UnsupportedCompressionMethod(128))
+    ));
+    assert!(matches!(
+        decompress(include_bytes!("../data/corrupted/08-bad-nlen.gz")),
+        Err(DecodeError::StoredBlockLengthMismatch { .. })
+    ));
+}
+
+#[test]
+fn unsupported_compression_method() {
+    use ripgzip::DecodeError;
+
+    let header: &[u8] = &[0x1f, 0x8b, 3, 0, 0, 0, 0, 0, 0, 3];
+    let err = ripgzip::decompress(header, std::io::sink()).unwrap_err();
+    assert!(matches!(err, DecodeError::UnsupportedCompressionMethod(3)));
+    assert_eq!(
+        err.to_string(),
+        "unsupported compression method 3 (reserved)"
+    );
+
+    let err = ripgzip::decompress(
+        &include_bytes!("../data/corrupted/07-invalid-cm.gz")[..],
+        std::io::sink(),
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "unsupported compression method 128 (undefined)"
+    );
+}
+
+#[test]
+fn footer_mismatch() {
+    use ripgzip::DecodeError;
+
+    let data: &[u8] = include_bytes!("../data/ok/00-Cargo.toml.gz");
+    let len = data.len();
+    let crc = u32::from_le_bytes(data[len - 8..len - 4].try_into().unwrap());
+
+    let mut bad_size = data.to_vec();
+    bad_size[len - 4] += 1;
+    let err = ripgzip::decompress(bad_size.as_slice(), std::io::sink()).unwrap_err();
+    assert!(matches!(
+        err,
+        DecodeError::LengthMismatch {
+            expected: 296,
+            actual: 295
+        }
+    ));
+    assert_eq!(
+        err.to_string(),
+        "length check failed: ISIZE is 296, the data has 295 bytes"
+    );
+
+    let mut bad_crc = data.to_vec();
+    bad_crc[len - 8] ^= 1;
+    let err = ripgzip::decompress(bad_crc.as_slice(), std::io::sink()).unwrap_err();
+    assert!(matches!(
+        err,
+        DecodeError::CrcMismatch { expected, actual } if expected == crc ^ 1 && actual == crc
+    ));
+}
@@ -0,0 +1,274 @@
+use ripgzip::{CompressionMethod, DeflateLevelHint, ExtraSubField, MemberHeader, OperatingSystem};
+
+#[test]
+fn headers() {
+    let mut data: &[u8] = include_bytes!("../data/ok/11-named-concat.gz");
+    let mut output = vec![];
+    let headers = ripgzip::decompress_with_headers(&mut data, &mut output).unwrap();
+    assert_eq!(output, b"Hello, world!\nSecond member.\n");
+
+    assert_eq!(headers.len(), 2);
+    assert_eq!(headers[0].compression_method, CompressionMethod::Deflate);
+    assert_eq!(headers[0].name.as_deref(), Some("hello.txt"));
+    assert_eq!(headers[0].modification_time, 1617120213);
+    assert_eq!(headers[0].comment, None);
+    assert_eq!(headers[1].name.as_deref(), Some("second.txt"));
+    assert_eq!(headers[1].modification_time, 1617639609);
+}
+
+#[test]
+fn modified_at() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let mut data: &[u8] = include_bytes!("../data/ok/11-named-concat.gz");
+    let headers = ripgzip::decompress_with_headers(&mut data, std::io::sink()).unwrap();
+    assert_eq!(
+        headers[0].modified_at(),
+        Some(UNIX_EPOCH + Duration::from_secs(1617120213))
+    );
+    assert_eq!(MemberHeader::default().modified_at(), None);
+}
+
+#[test]
+fn headers_per_member() {
+    let mut data: &[u8] = include_bytes!("../data/ok/09-concat.gz");
+    let headers = ripgzip::decompress_with_headers(&mut data, std::io::sink()).unwrap();
+    assert_eq!(headers.len(), 3);
+    assert!(headers.iter().all(|h| h.name.is_none() && h.os == 3));
+}
+
+#[test]
+fn read_header() {
+    let data: &[u8] = include_bytes!("../data/ok/11-named-concat.gz");
+    let mut reader = ripgzip::GzipReader::new(data);
+    let header = reader.read_header().unwrap();
+    assert_eq!(header.name.as_deref(), Some("hello.txt"));
+    assert_eq!(reader.read_header().unwrap().name, header.name);
+
+    let (decompressed_header, rest, output) = reader.decompress(vec![]).unwrap();
+    assert_eq!(decompressed_header.name, header.name);
+    assert_eq!(output, b"Hello, world!\n");
+
+    let mut reader = ripgzip::GzipReader::new(rest);
+    assert_eq!(
+        reader.read_header().unwrap().name.as_deref(),
+        Some("second.txt")
+    );
+}
+
+#[test]
+fn members() {
+    use ripgzip::{DecodeError, GzipReader};
+
+    let data: &[u8] = include_bytes!("../data/ok/11-named-concat.gz");
+    let members: Vec<_> = GzipReader::members(data)
+        .map(|member| member.unwrap())
+        .map(|(header, data)| (header.name.unwrap(), data))
+        .collect();
+    assert_eq!(
+        members,
+        [
+            ("hello.txt".to_owned(), b"Hello, world!\n".to_vec()),
+            ("second.txt".to_owned(), b"Second member.\n".to_vec()),
+        ]
+    );
+
+    assert_eq!(GzipReader::members(&b""[..]).count(), 0);
+
+    /* The member before the broken one is still yielded. */
+    let input = [data, b"garbage"].concat();
+    let mut members = GzipReader::members(input.as_slice());
+    assert!(members.next().unwrap().is_ok());
+    assert!(members.next().unwrap().is_ok());
+    assert!(matches!(members.next(), Some(Err(DecodeError::BadMagic))));
+    assert!(members.next().is_none());
+}
+
+#[test]
+fn decompress_one() {
+    let member: &[u8] = include_bytes!("../data/ok/00-Cargo.toml.gz");
+    let input = [member, b"next frame"].concat();
+    let (rest, output, footer) = ripgzip::GzipReader::new(input.as_slice())
+        .decompress_one(vec![])
+        .unwrap();
+    assert_eq!(rest, b"next frame");
+    assert_eq!(output.len(), 295);
+    assert_eq!(footer.data_size, 295);
+    assert_eq!(
+        footer.data_crc32,
+        u32::from_le_bytes(
+            member[member.len() - 8..member.len() - 4]
+                .try_into()
+                .unwrap()
+        )
+    );
+}
+
+#[test]
+fn member_reader() {
+    use std::io::Read;
+
+    let data: &[u8] = include_bytes!("../data/ok/09-concat.gz");
+    let mut member = ripgzip::GzipReader::new(data).member_reader();
+    let mut first = vec![];
+    member.read_to_end(&mut first).unwrap();
+    assert_eq!(first.len(), 88194);
+    assert_eq!(member.read(&mut [0; 16]).unwrap(), 0);
+
+    let rest = member.into_inner().unwrap();
+    let mut output = ripgzip::decompress_to_vec(data).unwrap();
+    assert_eq!(output.drain(..first.len()).as_slice(), first);
+    assert_eq!(ripgzip::decompress_to_vec(rest).unwrap(), output);
+
+    let mut member = ripgzip::GzipReader::new(data).member_reader();
+    member.read_exact(&mut [0; 10]).unwrap();
+    assert!(member.into_inner().is_none());
+}
+
+#[test]
+fn member_reader_options() {
+    use std::io::Read;
+
+    /* The top bit of the last deflate byte only pads the final block. */
+    let data: &[u8] = include_bytes!("../data/ok/00-Cargo.toml.gz");
+    let mut bad_padding = data.to_vec();
+    let len = bad_padding.len();
+    bad_padding[len - 9] |= 0x80;
+    let mut output = vec![];
+    ripgzip::GzipReader::new(bad_padding.as_slice())
+        .tree_cache(4)
+        .member_reader()
+        .read_to_end(&mut output)
+        .unwrap();
+    assert_eq!(output.len(), 295);
+    assert!(ripgzip::GzipReader::new(bad_padding.as_slice())
+        .strict_padding(true)
+        .member_reader()
+        .read_to_end(&mut vec![])
+        .is_err());
+}
+
+#[test]
+fn peek_member_header() {
+    use ripgzip::{DecodeError, EofContext};
+
+    let data: &[u8] = include_bytes!("../data/ok/11-named-concat.gz");
+    let header = ripgzip::peek_member_header(data).unwrap();
+    assert_eq!(header.name.as_deref(), Some("hello.txt"));
+    /* The fixed fields and the name with its terminating zero. */
+    let header_len = 10 + "hello.txt".len() + 1;
+    assert!(ripgzip::peek_member_header(&data[..header_len]).is_ok());
+    for len in 0..header_len {
+        assert!(matches!(
+            ripgzip::peek_member_header(&data[..len]),
+            Err(DecodeError::UnexpectedEof {
+                context: EofContext::Header
+            })
+        ));
+        assert_eq!(ripgzip::is_gzip(&data[..len]), len >= 3);
+    }
+    assert!(ripgzip::is_gzip(data));
+
+    let data: &[u8] = include_bytes!("../data/corrupted/03-wrong-id.gz");
+    assert!(!ripgzip::is_gzip(data));
+    let data: &[u8] = include_bytes!("../data/corrupted/07-invalid-cm.gz");
+    assert!(!ripgzip::is_gzip(data));
+    assert!(!ripgzip::is_gzip(b"plain text"));
+}
+
+#[test]
+fn index() {
+    let first: &[u8] = include_bytes!("../data/ok/00-Cargo.toml.gz");
+    let second: &[u8] = include_bytes!("../data/ok/10-header-crc16.gz");
+    let input = [first, second].concat();
+    let members = ripgzip::index(input.as_slice()).unwrap();
+    assert_eq!(members.len(), 2);
+    assert_eq!(members[0].compressed_len, first.len() as u64);
+    assert_eq!(members[0].uncompressed_len, 295);
+    assert_eq!(members[1].compressed_len, second.len() as u64);
+    assert!(members[1].header.has_crc);
+
+    /* Each member can be decompressed on its own from the offset in the index. */
+    let offset = members[0].compressed_len as usize;
+    let (info, rest, output) = ripgzip::GzipReader::new(&input[offset..])
+        .decompress_with_info(vec![])
+        .unwrap();
+    assert!(rest.is_empty());
+    assert_eq!(info.uncompressed_len, output.len() as u64);
+    assert_eq!(info.footer, members[1].footer);
+
+    let data: &[u8] = include_bytes!("../data/ok/11-named-concat.gz");
+    let members = ripgzip::index(data).unwrap();
+    let total: u64 = members.iter().map(|info| info.compressed_len).sum();
+    assert_eq!(total, data.len() as u64);
+}
+
+#[test]
+fn level_hint() {
+    /* As written by `gzip -9` for empty input. */
+    let data: &[u8] = &[
+        0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 2, 3, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    let header = ripgzip::GzipReader::new(data).read_header().unwrap();
+    assert_eq!(header.level_hint(), DeflateLevelHint::MaxCompression);
+    assert_eq!(header.extra_flags, 2);
+
+    assert_eq!(DeflateLevelHint::from(2), DeflateLevelHint::MaxCompression);
+    assert_eq!(DeflateLevelHint::from(4), DeflateLevelHint::Fastest);
+    assert_eq!(DeflateLevelHint::from(0), DeflateLevelHint::Unspecified(0));
+    for xfl in 0..=255 {
+        assert_eq!(u8::from(DeflateLevelHint::from(xfl)), xfl);
+    }
+}
+
+#[test]
+fn operating_system() {
+    let mut data: &[u8] = include_bytes!("../data/ok/00-Cargo.toml.gz");
+    let headers = ripgzip::decompress_with_headers(&mut data, std::io::sink()).unwrap();
+    assert_eq!(headers[0].os_kind(), OperatingSystem::Unix);
+
+    assert_eq!(OperatingSystem::from(11), OperatingSystem::Ntfs);
+    assert_eq!(OperatingSystem::from(42), OperatingSystem::Unknown);
+    for os in 0..=13 {
+        assert_eq!(u8::from(OperatingSystem::from(os)), os);
+    }
+    assert_eq!(u8::from(OperatingSystem::Unknown), 255);
+}
+
+#[test]
+fn extra_subfields() {
+    let header = MemberHeader {
+        extra: Some(vec![b'B', b'C', 2, 0, 0x1b, 0x00, b'A', b'p', 0, 0]),
+        ..Default::default()
+    };
+    assert_eq!(
+        header.extra_subfields().unwrap(),
+        vec![
+            ExtraSubField {
+                id: [b'B', b'C'],
+                data: vec![0x1b, 0x00],
+            },
+            ExtraSubField {
+                id: [b'A', b'p'],
+                data: vec![],
+            },
+        ]
+    );
+
+    assert!(MemberHeader::default()
+        .extra_subfields()
+        .unwrap()
+        .is_empty());
+
+    let overrun = MemberHeader {
+        extra: Some(vec![b'B', b'C', 3, 0, 0x1b, 0x00]),
+        ..Default::default()
+    };
+    assert!(overrun.extra_subfields().is_err());
+
+    let truncated = MemberHeader {
+        extra: Some(vec![b'B', b'C', 0]),
+        ..Default::default()
+    };
+    assert!(truncated.extra_subfields().is_err());
+}
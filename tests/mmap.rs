@@ -0,0 +1,40 @@
+#![cfg(feature = "mmap")]
+
+use std::{fs::File, path::Path};
+
+fn data_path(name: &str) -> std::path::PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("data")
+        .join(name)
+}
+
+#[test]
+fn decompress_mmap() {
+    for name in ["ok/06-war-and-peace.txt.gz", "ok/11-named-concat.gz"] {
+        let path = data_path(name);
+        let expected = ripgzip::decompress_slice(&std::fs::read(&path).unwrap()).unwrap();
+        let mut output = vec![];
+        ripgzip::decompress_mmap(&path, &mut output).unwrap();
+        assert_eq!(output, expected);
+    }
+
+    assert!(matches!(
+        ripgzip::decompress_mmap(&data_path("corrupted/01-bad-crc32.gz"), std::io::sink()),
+        Err(ripgzip::DecodeError::CrcMismatch { .. })
+    ));
+    assert!(matches!(
+        ripgzip::decompress_mmap(&data_path("no-such-file.gz"), std::io::sink()),
+        Err(ripgzip::DecodeError::Io(_))
+    ));
+}
+
+#[test]
+fn decompress_mapped_slice() {
+    let file = File::open(data_path("ok/06-war-and-peace.txt.gz")).unwrap();
+    /* The test data isn't changed while the tests run. */
+    #[allow(unsafe_code)]
+    let map = unsafe { memmap2::Mmap::map(&file).unwrap() };
+    let mut output = vec![];
+    ripgzip::decompress(&map[..], &mut output).unwrap();
+    assert_eq!(output, ripgzip::decompress_slice(&map).unwrap());
+}
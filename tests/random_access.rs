@@ -0,0 +1,55 @@
+use std::io::Cursor;
+
+fn check_slices(data: &[u8], span: u64) -> ripgzip::GzipIndex {
+    let expected = ripgzip::decompress_slice(data).unwrap();
+    let index = ripgzip::build_index(data, span).unwrap();
+    assert_eq!(index.len, expected.len() as u64);
+    assert_eq!(index.points()[0].offset, 0);
+
+    let len = expected.len();
+    for (offset, slice_len) in [
+        (0, 100),
+        (1, 70_000),
+        (len / 3, 1000),
+        (len / 2, 0),
+        (len - 10, 10),
+        (len - 10, 100),
+        (len, 10),
+    ] {
+        let slice = ripgzip::read_at(&index, Cursor::new(data), offset as u64, slice_len).unwrap();
+        let end = (offset + slice_len).min(len);
+        assert_eq!(slice, &expected[offset..end], "at {offset}");
+    }
+    index
+}
+
+#[test]
+fn huffman_blocks() {
+    let data: &[u8] = include_bytes!("../data/ok/06-war-and-peace.txt.gz");
+    let index = check_slices(data, 100_000);
+    assert!(index.points().len() > 10);
+    for pair in index.points().windows(2) {
+        assert!(pair[1].offset - pair[0].offset >= 100_000);
+        assert!(pair[1].bit_offset > pair[0].bit_offset);
+    }
+}
+
+#[test]
+fn stored_blocks() {
+    let plain: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    let mut data = vec![];
+    ripgzip::store(&plain, &mut data).unwrap();
+    let index = check_slices(&data, 50_000);
+    assert!(index.points().len() > 1);
+}
+
+#[test]
+fn corrupted_footer() {
+    let mut data = include_bytes!("../data/ok/01-page.gz").to_vec();
+    let len = data.len();
+    data[len - 8] ^= 1;
+    assert!(matches!(
+        ripgzip::build_index(&data[..], 1000),
+        Err(ripgzip::DecodeError::CrcMismatch { .. })
+    ));
+}
@@ -0,0 +1,87 @@
+use std::io::{self, BufRead, Read};
+
+/// Reader handing out a single byte per call, like a pipe fed one byte at a time.
+struct OneByteReader<'a> {
+    data: &'a [u8],
+}
+
+impl Read for OneByteReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.data.len()).min(1);
+        buf[..len].copy_from_slice(&self.data[..len]);
+        self.data = &self.data[len..];
+        Ok(len)
+    }
+}
+
+impl BufRead for OneByteReader<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        Ok(&self.data[..self.data.len().min(1)])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.data = &self.data[amt..];
+    }
+}
+
+fn one_byte(data: &[u8]) -> OneByteReader<'_> {
+    OneByteReader { data }
+}
+
+#[test]
+fn decompress() {
+    let mut stored = vec![];
+    ripgzip::store(&[42; 100_000], &mut stored).unwrap();
+    for data in [
+        &include_bytes!("../data/ok/00-Cargo.toml.gz")[..],
+        include_bytes!("../data/ok/01-page.gz"),
+        include_bytes!("../data/ok/10-header-crc16.gz"),
+        include_bytes!("../data/ok/11-named-concat.gz"),
+        include_bytes!("../data/ok/12-bgzf.gz"),
+        &stored,
+    ] {
+        let expected = ripgzip::decompress_slice(data).unwrap();
+        let mut output = vec![];
+        ripgzip::decompress(one_byte(data), &mut output).unwrap();
+        assert_eq!(output, expected);
+
+        let mut output = vec![];
+        ripgzip::GzipDecoder::new(one_byte(data))
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, expected);
+    }
+}
+
+#[test]
+fn members_and_footers() {
+    let data: &[u8] = include_bytes!("../data/ok/11-named-concat.gz");
+    let headers = ripgzip::decompress_with_headers(one_byte(data), io::sink()).unwrap();
+    assert_eq!(headers[0].name.as_deref(), Some("hello.txt"));
+    let footers = ripgzip::verify(one_byte(data)).unwrap();
+    assert_eq!(footers[1].data_size, 15);
+
+    /* The rest of the input stays in the reader, even if it comes byte by byte. */
+    let mut member = ripgzip::GzipReader::new(one_byte(data)).member_reader();
+    let mut output = String::new();
+    member.read_to_string(&mut output).unwrap();
+    assert_eq!(output, "Hello, world!\n");
+    let mut output = vec![];
+    ripgzip::decompress(member.into_inner().unwrap(), &mut output).unwrap();
+    assert_eq!(output, b"Second member.\n");
+}
+
+#[test]
+fn other_formats() {
+    let data: &[u8] = include_bytes!("../data/zlib/00-Cargo.toml.zz");
+    let mut output = vec![];
+    ripgzip::decompress_zlib(one_byte(data), &mut output).unwrap();
+    assert_eq!(output.len(), 295);
+
+    let data: &[u8] = include_bytes!("../data/ok/12-bgzf.gz");
+    let mut output = vec![];
+    ripgzip::BgzfReader::new(one_byte(data))
+        .read_to_end(&mut output)
+        .unwrap();
+    assert_eq!(output.len(), 200_000);
+}
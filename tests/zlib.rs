@@ -0,0 +1,78 @@
+use ripgzip::{DecodeError, EofContext};
+
+#[test]
+fn decompress_zlib() {
+    let gzip: &[u8] = include_bytes!("../data/ok/00-Cargo.toml.gz");
+    let expected = ripgzip::decompress_to_vec(gzip).unwrap();
+
+    let mut data: &[u8] = include_bytes!("../data/zlib/00-Cargo.toml.zz");
+    let mut output = vec![];
+    ripgzip::decompress_zlib(&mut data, &mut output).unwrap();
+    assert_eq!(output, expected);
+    assert!(data.is_empty());
+}
+
+#[test]
+fn decompress_zlib_with_dictionary() {
+    let gzip: &[u8] = include_bytes!("../data/ok/00-Cargo.toml.gz");
+    let expected = ripgzip::decompress_to_vec(gzip).unwrap();
+
+    let dictionary = b"[package]\nname = \"ripgzip\"\n";
+    let mut data: &[u8] = include_bytes!("../data/zlib/01-dictionary.zz");
+    let mut output = vec![];
+    ripgzip::decompress_zlib_with_dictionary(&mut data, &mut output, dictionary).unwrap();
+    assert_eq!(output, expected);
+    assert!(data.is_empty());
+
+    assert!(matches!(
+        ripgzip::decompress_zlib_with_dictionary(
+            &include_bytes!("../data/zlib/01-dictionary.zz")[..],
+            std::io::sink(),
+            b"[package]\n",
+        ),
+        Err(DecodeError::MissingDictionary(0x84990900))
+    ));
+}
+
+#[test]
+fn zlib_errors() {
+    let data: &[u8] = include_bytes!("../data/zlib/00-Cargo.toml.zz");
+    let decompress = |data: &[u8]| ripgzip::decompress_zlib(data, std::io::sink());
+
+    let mut corrupted = data.to_vec();
+    *corrupted.last_mut().unwrap() ^= 1;
+    assert!(matches!(
+        decompress(&corrupted),
+        Err(DecodeError::Adler32Mismatch)
+    ));
+
+    assert!(matches!(
+        decompress(&data[..data.len() - 2]),
+        Err(DecodeError::UnexpectedEof {
+            context: EofContext::Footer
+        })
+    ));
+    assert!(matches!(
+        decompress(&data[..1]),
+        Err(DecodeError::UnexpectedEof {
+            context: EofContext::Header
+        })
+    ));
+
+    let mut corrupted = data.to_vec();
+    corrupted[1] ^= 1;
+    assert!(matches!(
+        decompress(&corrupted),
+        Err(DecodeError::InvalidHeader(_))
+    ));
+
+    assert!(matches!(
+        decompress(&[0x79, 0x18]),
+        Err(DecodeError::UnsupportedCompressionMethod(9))
+    ));
+
+    assert!(matches!(
+        decompress(include_bytes!("../data/zlib/01-dictionary.zz")),
+        Err(DecodeError::MissingDictionary(0x84990900))
+    ));
+}